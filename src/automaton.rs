@@ -0,0 +1,706 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+// Cells are stored row by row in a single Vec, neighbourhoods and rules talk
+// in terms of these flat indices
+#[derive(Debug, PartialEq, Clone)]
+pub struct Grid<C> {
+    width: usize,
+    height: usize,
+    cells: Vec<C>,
+}
+
+impl<C> Grid<C> {
+    pub fn from_rows(rows: Vec<Vec<C>>) -> Self {
+        let height = rows.len();
+        let width = rows.first().map_or(0, |r| r.len());
+        assert!(
+            rows.iter().all(|r| r.len() == width),
+            "Rows must all be the same size!"
+        );
+        let cells: Vec<C> = rows.into_iter().flatten().collect();
+
+        Grid {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn cells(&self) -> &[C] {
+        &self.cells
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> &C {
+        &self.cells[self.index(row, col)]
+    }
+
+    pub fn index(&self, row: usize, col: usize) -> usize {
+        row * self.width + col
+    }
+
+    pub fn position(&self, index: usize) -> (usize, usize) {
+        (index / self.width, index % self.width)
+    }
+
+    // Moves from a cell by (d_row, d_col), None if that leaves the grid
+    pub fn offset(&self, index: usize, d_row: i64, d_col: i64) -> Option<usize> {
+        let (row, col) = self.position(index);
+        let row = row as i64 + d_row;
+        let col = col as i64 + d_col;
+
+        if row < 0 || row >= self.height as i64 || col < 0 || col >= self.width as i64 {
+            None
+        } else {
+            Some(self.index(row as usize, col as usize))
+        }
+    }
+
+    pub fn count(&self, predicate: impl Fn(&C) -> bool) -> usize {
+        self.cells.iter().filter(|c| predicate(c)).count()
+    }
+
+    pub fn to_text(&self, symbol: impl Fn(&C) -> char) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+        for row in self.cells.chunks(self.width.max(1)) {
+            text.extend(row.iter().map(&symbol));
+            text.push('\n');
+        }
+
+        text
+    }
+
+    // Binary PPM (P6), each cell drawn as a scale x scale square
+    pub fn write_ppm<W: std::io::Write>(
+        &self,
+        out: &mut W,
+        scale: usize,
+        colour: impl Fn(&C) -> [u8; 3],
+    ) -> std::io::Result<()> {
+        write!(
+            out,
+            "P6\n{} {}\n255\n",
+            self.width * scale,
+            self.height * scale
+        )?;
+
+        for row in self.cells.chunks(self.width.max(1)) {
+            let mut line = Vec::with_capacity(row.len() * scale * 3);
+            for cell in row {
+                let rgb = colour(cell);
+                for _ in 0..scale {
+                    line.extend_from_slice(&rgb);
+                }
+            }
+            for _ in 0..scale {
+                out.write_all(&line)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<C: Clone + PartialEq> Grid<C> {
+    // Applies the rule to every cell at once, returns whether anything changed
+    pub fn step<N, R>(&mut self, neighbourhood: &N, rule: &R) -> bool
+    where
+        N: Neighbourhood<C>,
+        R: Rule<C>,
+    {
+        // Neighbourhoods can depend on cell states too, so everything looks at
+        // the grid as it was before this step
+        let original = self.clone();
+        let mut changed = false;
+
+        for i in 0..self.cells.len() {
+            let indices = neighbourhood.neighbours(&original, i);
+            let neighbours = Neighbours {
+                cells: &original.cells,
+                indices: indices.iter(),
+            };
+
+            let next = rule.apply(&original.cells[i], neighbours);
+            if next != original.cells[i] {
+                changed = true;
+                self.cells[i] = next;
+            }
+        }
+
+        changed
+    }
+}
+
+pub const DIRECTIONS: [(i64, i64); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+pub trait Neighbourhood<C> {
+    fn neighbours(&self, grid: &Grid<C>, index: usize) -> Vec<usize>;
+}
+
+// The (up to) eight cells touching this one
+pub struct Adjacent;
+
+impl<C> Neighbourhood<C> for Adjacent {
+    fn neighbours(&self, grid: &Grid<C>, index: usize) -> Vec<usize> {
+        DIRECTIONS
+            .iter()
+            .filter_map(|&(d_row, d_col)| grid.offset(index, d_row, d_col))
+            .collect()
+    }
+}
+
+// The first visible cell in each of the eight directions, skipping over cells
+// for which `is_visible` is false
+pub struct LineOfSight<F> {
+    pub is_visible: F,
+}
+
+impl<C, F: Fn(&C) -> bool> Neighbourhood<C> for LineOfSight<F> {
+    fn neighbours(&self, grid: &Grid<C>, index: usize) -> Vec<usize> {
+        let mut found = Vec::new();
+
+        for &(d_row, d_col) in DIRECTIONS.iter() {
+            let mut current = grid.offset(index, d_row, d_col);
+            while let Some(i) = current {
+                if (self.is_visible)(&grid.cells[i]) {
+                    found.push(i);
+                    break;
+                }
+                current = grid.offset(i, d_row, d_col);
+            }
+        }
+
+        found
+    }
+}
+
+pub struct Custom<F>(pub F);
+
+impl<C, F: Fn(&Grid<C>, usize) -> Vec<usize>> Neighbourhood<C> for Custom<F> {
+    fn neighbours(&self, grid: &Grid<C>, index: usize) -> Vec<usize> {
+        (self.0)(grid, index)
+    }
+}
+
+// States of the neighbours of a cell as of the previous generation
+pub struct Neighbours<'a, C> {
+    cells: &'a [C],
+    indices: std::slice::Iter<'a, usize>,
+}
+
+impl<'a, C> Iterator for Neighbours<'a, C> {
+    type Item = &'a C;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.indices.next().map(|&i| &self.cells[i])
+    }
+}
+
+pub trait Rule<C> {
+    fn apply(&self, cell: &C, neighbours: Neighbours<'_, C>) -> C;
+}
+
+// Neighbour lists for every cell, flattened so that the neighbours of cell i
+// are targets[offsets[i]..offsets[i + 1]]. Building this once only makes
+// sense when the neighbourhood doesn't depend on cells that can change, e.g.
+// line of sight through floor that stays floor forever.
+#[derive(Debug, PartialEq)]
+pub struct NeighbourGraph {
+    offsets: Vec<usize>,
+    targets: Vec<usize>,
+}
+
+impl NeighbourGraph {
+    pub fn build<C, N: Neighbourhood<C>>(grid: &Grid<C>, neighbourhood: &N) -> Self {
+        let mut offsets = vec![0];
+        let mut targets = Vec::new();
+        for i in 0..grid.cells.len() {
+            targets.extend(neighbourhood.neighbours(grid, i));
+            offsets.push(targets.len());
+        }
+
+        NeighbourGraph { offsets, targets }
+    }
+
+    pub fn neighbours(&self, index: usize) -> &[usize] {
+        &self.targets[self.offsets[index]..self.offsets[index + 1]]
+    }
+
+    // Same graph with every edge flipped, i.e. who looks at each cell
+    fn reversed(&self) -> Self {
+        let cells = self.offsets.len() - 1;
+        let mut counts = vec![0; cells + 1];
+        for &t in self.targets.iter() {
+            counts[t + 1] += 1;
+        }
+        for i in 0..cells {
+            counts[i + 1] += counts[i];
+        }
+
+        let offsets = counts.clone();
+        let mut targets = vec![0; self.targets.len()];
+        for i in 0..cells {
+            for &t in self.neighbours(i) {
+                targets[counts[t]] = i;
+                counts[t] += 1;
+            }
+        }
+
+        NeighbourGraph { offsets, targets }
+    }
+}
+
+// Bundles a grid with how it evolves, for when the rules don't change between
+// steps. Neighbours are looked up once, the next generation is written to a
+// second buffer that then gets swapped in, and only cells next to something
+// that changed in the last step are looked at again.
+pub struct Automaton<C, R> {
+    grid: Grid<C>,
+    back: Vec<C>,
+    graph: NeighbourGraph,
+    dependents: NeighbourGraph,
+    rule: R,
+    dirty: Vec<usize>,
+    is_dirty: Vec<bool>,
+}
+
+impl<C, R> Automaton<C, R>
+where
+    C: Clone + PartialEq,
+    R: Rule<C>,
+{
+    pub fn new<N: Neighbourhood<C>>(grid: Grid<C>, neighbourhood: N, rule: R) -> Self {
+        let graph = NeighbourGraph::build(&grid, &neighbourhood);
+        let dependents = graph.reversed();
+        let cells = grid.cells.len();
+
+        Automaton {
+            back: grid.cells.clone(),
+            grid,
+            graph,
+            dependents,
+            rule,
+            dirty: (0..cells).collect(),
+            is_dirty: vec![true; cells],
+        }
+    }
+
+    pub fn grid(&self) -> &Grid<C> {
+        &self.grid
+    }
+
+    pub fn into_grid(self) -> Grid<C> {
+        self.grid
+    }
+
+    pub fn step(&mut self) -> bool {
+        self.advance() > 0
+    }
+
+    // Same as step, but says how many cells changed
+    pub fn advance(&mut self) -> usize {
+        let mut changed = Vec::new();
+        for &i in self.dirty.iter() {
+            let neighbours = Neighbours {
+                cells: &self.grid.cells,
+                indices: self.graph.neighbours(i).iter(),
+            };
+
+            let next = self.rule.apply(&self.grid.cells[i], neighbours);
+            if next != self.grid.cells[i] {
+                self.back[i] = next;
+                changed.push(i);
+            }
+        }
+
+        std::mem::swap(&mut self.grid.cells, &mut self.back);
+        // The old front is now the back buffer, bring it up to date
+        for &i in changed.iter() {
+            self.back[i] = self.grid.cells[i].clone();
+        }
+
+        for &i in self.dirty.iter() {
+            self.is_dirty[i] = false;
+        }
+        self.dirty.clear();
+        // A changed cell has to be looked at again too, rules can depend on
+        // the cell itself and not just its neighbours
+        for &i in changed.iter() {
+            for &d in std::iter::once(&i).chain(self.dependents.neighbours(i)) {
+                if !self.is_dirty[d] {
+                    self.is_dirty[d] = true;
+                    self.dirty.push(d);
+                }
+            }
+        }
+
+        changed.len()
+    }
+
+    // Steps until nothing changes, returns how many steps changed something
+    pub fn run(&mut self) -> usize {
+        let mut generations = 0;
+        while self.step() {
+            generations += 1;
+        }
+
+        generations
+    }
+
+    pub fn generations(self) -> Generations<C, R> {
+        Generations {
+            automaton: self,
+            number: 0,
+            done: false,
+        }
+    }
+}
+
+impl<C, R> Automaton<C, R>
+where
+    C: Clone + PartialEq + Hash,
+    R: Rule<C>,
+{
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.grid.cells.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Like run, but also notices when the grid starts repeating itself instead
+    // of settling, and gives up after max_generations steps. Only the hash of
+    // each generation is kept, so a collision could report a bogus cycle, but
+    // with 64 bits that's not something to lose sleep over.
+    pub fn run_until_settled(&mut self, max_generations: usize) -> Result<Outcome, BudgetExceeded> {
+        let mut seen = HashMap::new();
+        seen.insert(self.state_hash(), 0);
+
+        for generation in 1..=max_generations {
+            if !self.step() {
+                return Ok(Outcome::Stable {
+                    generations: generation - 1,
+                });
+            }
+
+            if let Some(start) = seen.insert(self.state_hash(), generation) {
+                return Ok(Outcome::Cycle {
+                    start,
+                    length: generation - start,
+                });
+            }
+        }
+
+        Err(BudgetExceeded {
+            generations: max_generations,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    // Stopped changing after this many generations
+    Stable { generations: usize },
+    // Generation start + length is the same as generation start
+    Cycle { start: usize, length: usize },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BudgetExceeded {
+    pub generations: usize,
+}
+
+impl std::fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Still changing after {} generations", self.generations)
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+pub struct Generation<C> {
+    pub number: usize,
+    pub changed: usize,
+    pub grid: Grid<C>,
+}
+
+// The starting grid, then every generation where something changed. The last
+// one yielded is the stable state, a rule that never settles never ends it.
+pub struct Generations<C, R> {
+    automaton: Automaton<C, R>,
+    number: usize,
+    done: bool,
+}
+
+impl<C, R> Iterator for Generations<C, R>
+where
+    C: Clone + PartialEq,
+    R: Rule<C>,
+{
+    type Item = Generation<C>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let changed = if self.number == 0 {
+            0
+        } else {
+            let changed = self.automaton.advance();
+            if changed == 0 {
+                self.done = true;
+                return None;
+            }
+            changed
+        };
+
+        self.number += 1;
+        Some(Generation {
+            number: self.number - 1,
+            changed,
+            grid: self.automaton.grid.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plain Conway's game of life
+    struct Life;
+
+    impl Rule<bool> for Life {
+        fn apply(&self, cell: &bool, neighbours: Neighbours<'_, bool>) -> bool {
+            let alive = neighbours.filter(|&&n| n).count();
+            alive == 3 || (*cell && alive == 2)
+        }
+    }
+
+    // Alive when exactly one other live cell is in sight
+    struct SeesOne;
+
+    impl Rule<bool> for SeesOne {
+        fn apply(&self, _: &bool, neighbours: Neighbours<'_, bool>) -> bool {
+            neighbours.count() == 1
+        }
+    }
+
+    // Only looks at the cell itself
+    struct Flip;
+
+    impl Rule<bool> for Flip {
+        fn apply(&self, cell: &bool, _: Neighbours<'_, bool>) -> bool {
+            !cell
+        }
+    }
+
+    #[test]
+    fn test_rule_reading_own_cell() {
+        let nobody = || Custom(|_: &Grid<bool>, _| Vec::new());
+        let mut grid = Grid::from_rows(vec![vec![true, false], vec![false, false]]);
+        let mut automaton = Automaton::new(grid.clone(), nobody(), Flip);
+
+        for _ in 0..4 {
+            assert!(grid.step(&nobody(), &Flip));
+            assert_eq!(automaton.advance(), 4);
+            assert_eq!(automaton.grid(), &grid);
+        }
+    }
+
+    #[test]
+    fn test_step_sees_previous_generation() {
+        // The first cell dies, but the others still have to see it while
+        // working out their own next state
+        let mut grid = Grid::from_rows(vec![vec![true, false, false]]);
+        let alive = LineOfSight {
+            is_visible: |&c: &bool| c,
+        };
+        assert!(grid.step(&alive, &SeesOne));
+        assert_eq!(grid.cells(), &[false, true, true]);
+    }
+
+    #[test]
+    fn test_grid() {
+        let grid = Grid::from_rows(vec![vec![1, 2, 3], vec![4, 5, 6]]);
+        assert_eq!((grid.width(), grid.height()), (3, 2));
+        assert_eq!(grid.get(1, 2), &6);
+        assert_eq!(grid.position(4), (1, 1));
+        assert_eq!(grid.offset(4, -1, 1), Some(2));
+        assert_eq!(grid.offset(4, 1, 0), None);
+        assert_eq!(grid.count(|&c| c % 2 == 0), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Rows must all be the same size")]
+    fn test_ragged_rows() {
+        Grid::from_rows(vec![vec![1, 2], vec![3, 4, 5], vec![6]]);
+    }
+
+    #[test]
+    fn test_neighbourhoods() {
+        let grid = Grid::from_rows(vec![vec![1, 0, 0, 1], vec![0, 0, 0, 0], vec![1, 0, 0, 1]]);
+        assert_eq!(Adjacent.neighbours(&grid, 0), vec![1, 4, 5]);
+        assert_eq!(
+            LineOfSight {
+                is_visible: |&c: &i32| c == 1
+            }
+            .neighbours(&grid, 0),
+            vec![3, 8]
+        );
+
+        let knights = Custom(|grid: &Grid<i32>, index| {
+            vec![(1, 2), (2, 1)]
+                .into_iter()
+                .filter_map(|(r, c)| grid.offset(index, r, c))
+                .collect()
+        });
+        assert_eq!(knights.neighbours(&grid, 0), vec![6, 9]);
+    }
+
+    #[test]
+    fn test_automaton() {
+        let blinker = Grid::from_rows(vec![
+            vec![false, false, false],
+            vec![true, true, true],
+            vec![false, false, false],
+        ]);
+        let mut automaton = Automaton::new(blinker.clone(), Adjacent, Life);
+
+        assert!(automaton.step());
+        assert_eq!(automaton.grid().count(|&c| c), 3);
+        assert!(*automaton.grid().get(0, 1) && !*automaton.grid().get(1, 0));
+        assert!(automaton.step());
+        assert_eq!(automaton.grid(), &blinker);
+
+        let block = Grid::from_rows(vec![vec![true, true], vec![true, true]]);
+        let mut automaton = Automaton::new(block.clone(), Adjacent, Life);
+        assert_eq!(automaton.run(), 0);
+        assert_eq!(automaton.into_grid(), block);
+    }
+
+    #[test]
+    fn test_neighbour_graph() {
+        let grid = Grid::from_rows(vec![vec![0, 0, 0], vec![0, 0, 0]]);
+        let graph = NeighbourGraph::build(&grid, &Adjacent);
+        assert_eq!(graph.neighbours(0), &[1, 3, 4]);
+        assert_eq!(graph.neighbours(4), &[0, 1, 2, 3, 5]);
+
+        // Only looks right, so cell 1 is only looked at by cell 0
+        let right = NeighbourGraph::build(
+            &grid,
+            &Custom(|grid: &Grid<i32>, i| grid.offset(i, 0, 1).into_iter().collect()),
+        );
+        let reversed = right.reversed();
+        assert_eq!(reversed.neighbours(0), &[] as &[usize]);
+        assert_eq!(reversed.neighbours(1), &[0]);
+        assert_eq!(reversed.neighbours(5), &[4]);
+    }
+
+    #[test]
+    fn test_automaton_matches_grid_step() {
+        let rows: Vec<Vec<bool>> = (0..12)
+            .map(|r| (0..12).map(|c| (r * 7 + c * 3) % 5 == 0).collect())
+            .collect();
+        let mut grid = Grid::from_rows(rows.clone());
+        let mut automaton = Automaton::new(Grid::from_rows(rows), Adjacent, Life);
+
+        for _ in 0..30 {
+            assert_eq!(grid.step(&Adjacent, &Life), automaton.step());
+            assert_eq!(&grid, automaton.grid());
+        }
+    }
+
+    #[test]
+    fn test_generations() {
+        let blinker_in_corner = Grid::from_rows(vec![
+            vec![true, true, false],
+            vec![true, false, false],
+            vec![false, false, false],
+        ]);
+        let generations: Vec<Generation<bool>> = Automaton::new(blinker_in_corner, Adjacent, Life)
+            .generations()
+            .collect();
+
+        assert_eq!(generations.len(), 2);
+        assert_eq!((generations[0].number, generations[0].changed), (0, 0));
+        assert_eq!((generations[1].number, generations[1].changed), (1, 1));
+        assert_eq!(
+            generations[1].grid.to_text(|&c| if c { '#' } else { '.' }),
+            "##.\n##.\n...\n"
+        );
+    }
+
+    #[test]
+    fn test_write_ppm() {
+        let grid = Grid::from_rows(vec![vec![true, false]]);
+        let mut out = Vec::new();
+        grid.write_ppm(&mut out, 2, |&c| if c { [255, 0, 0] } else { [0, 0, 0] })
+            .unwrap();
+
+        let header = b"P6\n4 2\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(out.len(), header.len() + 4 * 2 * 3);
+        assert_eq!(
+            &out[header.len()..header.len() + 6],
+            &[255, 0, 0, 255, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_run_until_settled() {
+        let blinker = Grid::from_rows(vec![
+            vec![false, false, false],
+            vec![true, true, true],
+            vec![false, false, false],
+        ]);
+        assert_eq!(
+            Automaton::new(blinker, Adjacent, Life).run_until_settled(100),
+            Ok(Outcome::Cycle {
+                start: 0,
+                length: 2
+            })
+        );
+
+        let mut corner = vec![vec![false; 4]; 4];
+        corner[0][0] = true;
+        corner[0][1] = true;
+        corner[1][0] = true;
+        assert_eq!(
+            Automaton::new(Grid::from_rows(corner), Adjacent, Life).run_until_settled(100),
+            Ok(Outcome::Stable { generations: 1 })
+        );
+
+        // A glider needs a few dozen generations to crash into the corner
+        let mut glider = vec![vec![false; 20]; 20];
+        glider[0][1] = true;
+        glider[1][2] = true;
+        glider[2][0] = true;
+        glider[2][1] = true;
+        glider[2][2] = true;
+        let mut automaton = Automaton::new(Grid::from_rows(glider), Adjacent, Life);
+        assert_eq!(
+            automaton.run_until_settled(10),
+            Err(BudgetExceeded { generations: 10 })
+        );
+        assert!(automaton.run_until_settled(1000).is_ok());
+    }
+}
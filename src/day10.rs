@@ -1,53 +1,340 @@
+use num_bigint::BigUint;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+
+pub struct ChainConfig {
+    pub allowed_gaps: Vec<usize>,
+    pub device_offset: usize,
+    pub outlet: usize,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig {
+            allowed_gaps: vec![1, 2, 3],
+            device_offset: 3,
+            outlet: 0,
+        }
+    }
+}
+
+pub struct Adapters {
+    // Outlet first, then the usable adapters sorted, then the device
+    nodes: Vec<usize>,
+    allowed_gaps: Vec<usize>,
+    max_gap: usize,
+}
+
+impl Adapters {
+    pub fn new(mut joltages: Vec<usize>, config: ChainConfig) -> Self {
+        joltages.sort_unstable();
+
+        // Adapters below the outlet can never be plugged into anything
+        let mut nodes = vec![config.outlet];
+        nodes.extend(joltages.into_iter().filter(|&j| j >= config.outlet));
+        nodes.push(nodes[nodes.len() - 1] + config.device_offset);
+
+        let max_gap = config.allowed_gaps.iter().copied().max().unwrap_or(0);
+
+        Adapters {
+            nodes,
+            allowed_gaps: config.allowed_gaps,
+            max_gap,
+        }
+    }
+
+    pub fn device(&self) -> usize {
+        self.nodes[self.nodes.len() - 1]
+    }
+
+    fn is_allowed(&self, from: usize, to: usize) -> bool {
+        self.allowed_gaps
+            .contains(&(self.nodes[to] - self.nodes[from]))
+    }
+
+    // Successors of a node are always after it, and since the nodes are sorted
+    // we can stop looking once the gap is bigger than anything allowed
+    fn successors(&self, from: usize) -> impl Iterator<Item = usize> + '_ {
+        (from + 1..self.nodes.len())
+            .take_while(move |&to| self.nodes[to] - self.nodes[from] <= self.max_gap)
+            .filter(move |&to| self.is_allowed(from, to))
+    }
+
+    // ways[i] is how many chains go from node i to the device
+    fn ways_to_device(&self) -> Vec<BigUint> {
+        let last = self.nodes.len() - 1;
+        let mut ways = vec![BigUint::from(0u32); self.nodes.len()];
+        ways[last] = BigUint::from(1u32);
+
+        for i in (0..last).rev() {
+            let mut total = BigUint::from(0u32);
+            for j in self.successors(i) {
+                total += &ways[j];
+            }
+            ways[i] = total;
+        }
+
+        ways
+    }
+
+    // ways[i] is how many chains go from the outlet to node i
+    fn ways_from_outlet(&self) -> Vec<BigUint> {
+        let mut ways = vec![BigUint::from(0u32); self.nodes.len()];
+        ways[0] = BigUint::from(1u32);
+
+        for i in 0..self.nodes.len() - 1 {
+            if ways[i] == BigUint::from(0u32) {
+                continue;
+            }
+            for j in self.successors(i) {
+                let from_i = ways[i].clone();
+                ways[j] += from_i;
+            }
+        }
+
+        ways
+    }
+
+    pub fn count_arrangements(&self) -> BigUint {
+        self.ways_to_device().swap_remove(0)
+    }
+
+    pub fn chains(&self) -> Chains<'_> {
+        let viable: Vec<bool> = self
+            .ways_to_device()
+            .iter()
+            .map(|w| *w != BigUint::from(0u32))
+            .collect();
+
+        let (path, cursors) = if viable[0] {
+            (vec![0], vec![1])
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        Chains {
+            adapters: self,
+            viable,
+            path,
+            cursors,
+        }
+    }
+
+    // Differences between neighbours when every adapter is used, as in part 1
+    pub fn gap_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for pair in self.nodes.windows(2) {
+            *histogram.entry(pair[1] - pair[0]).or_insert(0) += 1;
+        }
+
+        histogram
+    }
+
+    // Places between neighbouring nodes that no allowed jump crosses, so no
+    // chain can get past them. With gaps missing from allowed_gaps that isn't
+    // just neighbours being too far apart: with only 3 allowed, 1 -> 2 can't be
+    // crossed even though it's a small step.
+    pub fn unbridgeable_gaps(&self) -> Vec<Gap> {
+        let mut furthest = 0;
+        let mut gaps = Vec::new();
+
+        for position in 0..self.nodes.len() - 1 {
+            // Successors come in order, so the last one goes the furthest
+            let reach = self.successors(position).last().unwrap_or(position);
+            furthest = furthest.max(reach);
+            if furthest <= position {
+                gaps.push(Gap {
+                    position,
+                    from: self.nodes[position],
+                    to: self.nodes[position + 1],
+                });
+            }
+        }
+
+        gaps
+    }
+
+    // Smallest set of joltages to add so that at least one chain exists. This
+    // is a 0-1 BFS over every joltage between the outlet and the device, where
+    // landing on a joltage we don't have costs one new adapter. Returns None
+    // when no amount of adapters helps (e.g. only even gaps for an odd device).
+    pub fn missing_adapters(&self) -> Option<Vec<usize>> {
+        let outlet = self.nodes[0];
+        let device = self.device();
+        let present: HashSet<usize> = self.nodes.iter().copied().collect();
+
+        let size = device - outlet + 1;
+        let mut cost = vec![usize::MAX; size];
+        let mut previous = vec![None; size];
+        let mut queue = VecDeque::new();
+        cost[0] = 0;
+        queue.push_back(0);
+
+        while let Some(offset) = queue.pop_front() {
+            for &gap in self.allowed_gaps.iter().filter(|&&g| g > 0) {
+                let next = offset + gap;
+                if next >= size {
+                    continue;
+                }
+
+                let step = if present.contains(&(outlet + next)) {
+                    0
+                } else {
+                    1
+                };
+                if cost[offset] + step < cost[next] {
+                    cost[next] = cost[offset] + step;
+                    previous[next] = Some(offset);
+                    if step == 0 {
+                        queue.push_front(next);
+                    } else {
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        if cost[size - 1] == usize::MAX {
+            return None;
+        }
+
+        let mut missing = Vec::new();
+        let mut current = size - 1;
+        while let Some(prev) = previous[current] {
+            if !present.contains(&(outlet + current)) {
+                missing.push(outlet + current);
+            }
+            current = prev;
+        }
+        missing.reverse();
+
+        Some(missing)
+    }
+
+    // Adapters every valid chain has to go through, i.e. the ones where the
+    // chains reaching them and the chains leaving them account for all chains
+    pub fn mandatory_adapters(&self) -> Vec<usize> {
+        let to_device = self.ways_to_device();
+        let total = &to_device[0];
+        if *total == BigUint::from(0u32) {
+            return Vec::new();
+        }
+
+        let from_outlet = self.ways_from_outlet();
+        (1..self.nodes.len() - 1)
+            .filter(|&i| &from_outlet[i] * &to_device[i] == *total)
+            .map(|i| self.nodes[i])
+            .collect()
+    }
+
+    pub fn diagnose(&self) -> ChainDiagnostics {
+        ChainDiagnostics {
+            gap_histogram: self.gap_histogram(),
+            unbridgeable_gaps: self.unbridgeable_gaps(),
+            missing_adapters: self.missing_adapters(),
+            mandatory_adapters: self.mandatory_adapters(),
+        }
+    }
+}
+
+// Position is the index of the lower end in the sorted chain, the outlet being 0
+#[derive(Debug, PartialEq)]
+pub struct Gap {
+    pub position: usize,
+    pub from: usize,
+    pub to: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ChainDiagnostics {
+    pub gap_histogram: BTreeMap<usize, usize>,
+    pub unbridgeable_gaps: Vec<Gap>,
+    pub missing_adapters: Option<Vec<usize>>,
+    pub mandatory_adapters: Vec<usize>,
+}
+
+// Lazily walks every valid chain in lexicographic order, yielding the adapters
+// used (outlet and device not included). Dead ends are pruned up front, so
+// every step of the search leads to at least one chain.
+pub struct Chains<'a> {
+    adapters: &'a Adapters,
+    viable: Vec<bool>,
+    path: Vec<usize>,
+    cursors: Vec<usize>,
+}
+
+impl<'a> Iterator for Chains<'a> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let nodes = &self.adapters.nodes;
+        let device = nodes.len() - 1;
+
+        loop {
+            let current = *self.path.last()?;
+
+            if current == device {
+                let chain = self.path[1..self.path.len() - 1]
+                    .iter()
+                    .map(|&i| nodes[i])
+                    .collect();
+                self.path.pop();
+                self.cursors.pop();
+                return Some(chain);
+            }
+
+            let cursor = self.cursors[self.cursors.len() - 1];
+            let viable = &self.viable;
+            let next = self
+                .adapters
+                .successors(current)
+                .find(|&j| j >= cursor && viable[j]);
+
+            match next {
+                Some(j) => {
+                    *self.cursors.last_mut().unwrap() = j + 1;
+                    self.path.push(j);
+                    self.cursors.push(j + 1);
+                }
+                None => {
+                    self.path.pop();
+                    self.cursors.pop();
+                }
+            }
+        }
+    }
+}
+
 pub fn input_generator(input: &str) -> Vec<usize> {
     input.lines().map(|l| l.parse::<usize>().unwrap()).collect()
 }
 
 #[aoc(day10, part1)]
 pub fn part1(input: &str) -> usize {
-    let mut adapters = input_generator(input);
-    adapters.push(0);
-    adapters.sort();
-    adapters.push(adapters[adapters.len() - 1] + 3);
-
-    let mut one_diffs: usize = 0;
-    let mut three_diffs: usize = 0;
-
-    for i in 1..adapters.len() {
-        let diff = adapters[i] - adapters[i - 1];
-        match diff {
-            0 | 2 => (),
-            1 => one_diffs += 1,
-            3 => three_diffs += 1,
-            _ => panic!("Invalid input!"),
-        }
+    let adapters = Adapters::new(input_generator(input), ChainConfig::default());
+    let diagnostics = adapters.diagnose();
+
+    // The product is still the answer for the chain as given, so gaps too big
+    // to bridge are only reported
+    for gap in diagnostics.unbridgeable_gaps.iter() {
+        eprintln!(
+            "Can't go from {} to {} (position {})",
+            gap.from, gap.to, gap.position
+        );
+    }
+    if !diagnostics.unbridgeable_gaps.is_empty() {
+        eprintln!("Missing adapters: {:?}", diagnostics.missing_adapters);
     }
 
+    let one_diffs = diagnostics.gap_histogram.get(&1).unwrap_or(&0);
+    let three_diffs = diagnostics.gap_histogram.get(&3).unwrap_or(&0);
     one_diffs * three_diffs
 }
 
 #[aoc(day10, part2)]
 pub fn part2(input: &str) -> usize {
-    let mut adapters = input_generator(input);
-    adapters.push(0);
-    adapters.sort();
-    adapters.push(adapters[adapters.len() - 1] + 3);
-
-    let mut combinations = vec![1; adapters.len()];
-    for i in (0..adapters.len() - 3).rev() {
-        if adapters[i] == 10 {
-            println!("s");
-        }
-
-        if adapters[i + 3] - adapters[i] <= 3 {
-            combinations[i] = combinations[i + 3] + combinations[i + 2] + combinations[i + 1];
-        } else if adapters[i + 2] - adapters[i] <= 3 {
-            combinations[i] = combinations[i + 2] + combinations[i + 1];
-        } else if adapters[i + 1] - adapters[i] <= 3 {
-            combinations[i] = combinations[i + 1];
-        }
-    }
-
-    combinations[0]
+    let adapters = Adapters::new(input_generator(input), ChainConfig::default());
+    usize::try_from(&adapters.count_arrangements()).expect("Too many arrangements for usize!")
 }
 
 #[cfg(test)]
@@ -60,6 +347,9 @@ mod tests {
             part1("28\n33\n18\n42\n31\n14\n46\n20\n48\n47\n24\n23\n49\n45\n19\n38\n39\n11\n1\n32\n25\n35\n8\n17\n7\n9\n4\n2\n34\n10\n3"), 
             220
         );
+
+        // Gaps that can't be bridged don't stop it from counting
+        assert_eq!(part1("1\n2\n10\n11\n20"), 3);
     }
 
     #[test]
@@ -70,4 +360,143 @@ mod tests {
             19208
         );
     }
+
+    #[test]
+    fn test_count_arrangements() {
+        let adapters = Adapters::new(vec![1, 2, 3, 4], ChainConfig::default());
+        assert_eq!(adapters.device(), 7);
+        assert_eq!(adapters.count_arrangements(), BigUint::from(7u32));
+
+        let only_ones = ChainConfig {
+            allowed_gaps: vec![1],
+            device_offset: 1,
+            outlet: 0,
+        };
+        assert_eq!(
+            Adapters::new(vec![1, 2, 3, 4], only_ones).count_arrangements(),
+            BigUint::from(1u32)
+        );
+
+        let from_ten = ChainConfig {
+            allowed_gaps: vec![2, 3],
+            device_offset: 3,
+            outlet: 10,
+        };
+        let adapters = Adapters::new(vec![1, 12, 13, 15, 16, 18], from_ten);
+        assert_eq!(adapters.device(), 21);
+        assert_eq!(adapters.count_arrangements(), BigUint::from(3u32));
+
+        // 100 adapters in a row is way past what fits in a u64
+        let adapters = Adapters::new((1..=100).collect(), ChainConfig::default());
+        assert!(adapters.count_arrangements() > BigUint::from(u64::MAX));
+    }
+
+    #[test]
+    fn test_chains() {
+        let adapters = Adapters::new(
+            vec![16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4],
+            ChainConfig::default(),
+        );
+        let chains: Vec<Vec<usize>> = adapters.chains().collect();
+        assert_eq!(chains.len(), 8);
+        assert_eq!(chains[0], vec![1, 4, 5, 6, 7, 10, 11, 12, 15, 16, 19]);
+        assert_eq!(chains[7], vec![1, 4, 7, 10, 12, 15, 16, 19]);
+
+        let gapped = Adapters::new(vec![1, 8], ChainConfig::default());
+        assert_eq!(gapped.chains().next(), None);
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let adapters = Adapters::new(
+            vec![16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4],
+            ChainConfig::default(),
+        );
+        let diagnostics = adapters.diagnose();
+        assert_eq!(
+            diagnostics.gap_histogram,
+            vec![(1, 7), (3, 5)].into_iter().collect()
+        );
+        assert_eq!(diagnostics.unbridgeable_gaps, vec![]);
+        assert_eq!(diagnostics.missing_adapters, Some(vec![]));
+        assert_eq!(
+            diagnostics.mandatory_adapters,
+            vec![1, 4, 7, 10, 12, 15, 16, 19]
+        );
+
+        let adapters = Adapters::new(vec![1, 2, 10, 11, 20], ChainConfig::default());
+        let diagnostics = adapters.diagnose();
+        assert_eq!(
+            diagnostics.gap_histogram,
+            vec![(1, 3), (3, 1), (8, 1), (9, 1)].into_iter().collect()
+        );
+        assert_eq!(
+            diagnostics.unbridgeable_gaps,
+            vec![
+                Gap {
+                    position: 2,
+                    from: 2,
+                    to: 10
+                },
+                Gap {
+                    position: 4,
+                    from: 11,
+                    to: 20
+                },
+            ]
+        );
+        assert_eq!(diagnostics.missing_adapters, Some(vec![4, 7, 14, 17]));
+        assert_eq!(diagnostics.mandatory_adapters, vec![]);
+
+        let even_only = ChainConfig {
+            allowed_gaps: vec![2],
+            device_offset: 3,
+            outlet: 0,
+        };
+        let adapters = Adapters::new(vec![2, 4], even_only);
+        assert_eq!(adapters.missing_adapters(), None);
+        assert_eq!(
+            adapters.unbridgeable_gaps(),
+            vec![Gap {
+                position: 2,
+                from: 4,
+                to: 7
+            }]
+        );
+
+        // Small steps can be unbridgeable too when only bigger ones are allowed
+        let threes_only = ChainConfig {
+            allowed_gaps: vec![3],
+            device_offset: 3,
+            outlet: 0,
+        };
+        let adapters = Adapters::new(vec![1, 2], threes_only);
+        assert_eq!(adapters.count_arrangements(), BigUint::from(0u32));
+        assert_eq!(
+            adapters.unbridgeable_gaps(),
+            vec![
+                Gap {
+                    position: 0,
+                    from: 0,
+                    to: 1
+                },
+                Gap {
+                    position: 1,
+                    from: 1,
+                    to: 2
+                },
+            ]
+        );
+
+        // 0 -> 3 crosses both small steps, so nothing is in the way
+        let threes_only = ChainConfig {
+            allowed_gaps: vec![3],
+            device_offset: 3,
+            outlet: 0,
+        };
+        assert_eq!(
+            Adapters::new(vec![1, 2, 3], threes_only).unbridgeable_gaps(),
+            vec![]
+        );
+    }
 }
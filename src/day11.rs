@@ -1,151 +1,228 @@
-#[derive(Debug, PartialEq, Clone)]
+use crate::automaton::{
+    Adjacent, Automaton, BudgetExceeded, Generations, Grid, LineOfSight, Neighbours, Outcome, Rule,
+};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq, Clone, Hash)]
 pub enum SeatStatus {
     Empty,
     Occupied,
     Floor,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Seats {
-    layout: Vec<Vec<SeatStatus>>,
+impl SeatStatus {
+    pub fn symbol(&self) -> char {
+        match self {
+            SeatStatus::Empty => 'L',
+            SeatStatus::Occupied => '#',
+            SeatStatus::Floor => '.',
+        }
+    }
+
+    pub fn colour(&self) -> [u8; 3] {
+        match self {
+            SeatStatus::Empty => [40, 160, 70],
+            SeatStatus::Occupied => [200, 50, 40],
+            SeatStatus::Floor => [30, 30, 30],
+        }
+    }
 }
 
-impl Seats {
-    pub fn step1(&mut self) -> bool {
-        let mut changed = false;
-        let original = self.layout.clone();
-
-        for i in 0..self.layout.len() {
-            for j in 0..self.layout[i].len() {
-                match self.layout[i][j] {
-                    SeatStatus::Empty => {
-                        if Seats::adjacent_occupied(&original, i, j) == 0 {
-                            changed = true;
-                            self.layout[i][j] = SeatStatus::Occupied;
-                        }
-                    }
-                    SeatStatus::Occupied => {
-                        if Seats::adjacent_occupied(&original, i, j) >= 4 {
-                            changed = true;
-                            self.layout[i][j] = SeatStatus::Empty;
-                        }
-                    }
-                    SeatStatus::Floor => (),
+// Empty seats get taken when nobody is around, occupied ones are left once
+// `tolerance` neighbours are occupied
+pub struct SeatRule {
+    pub tolerance: usize,
+}
+
+impl Rule<SeatStatus> for SeatRule {
+    fn apply(&self, seat: &SeatStatus, neighbours: Neighbours<'_, SeatStatus>) -> SeatStatus {
+        match seat {
+            SeatStatus::Floor => SeatStatus::Floor,
+            _ => {
+                let occupied = neighbours.filter(|&n| n == &SeatStatus::Occupied).count();
+                match seat {
+                    SeatStatus::Empty if occupied == 0 => SeatStatus::Occupied,
+                    SeatStatus::Occupied if occupied >= self.tolerance => SeatStatus::Empty,
+                    _ => seat.clone(),
                 }
             }
         }
-
-        changed
     }
+}
 
-    pub fn step2(&mut self) -> bool {
-        let mut changed = false;
-        let original = self.layout.clone();
-
-        for i in 0..self.layout.len() {
-            for j in 0..self.layout[i].len() {
-                match self.layout[i][j] {
-                    SeatStatus::Empty => {
-                        if Seats::direction_occupied(&original, i, j) == 0 {
-                            changed = true;
-                            self.layout[i][j] = SeatStatus::Occupied;
-                        }
-                    }
-                    SeatStatus::Occupied => {
-                        if Seats::direction_occupied(&original, i, j) >= 5 {
-                            changed = true;
-                            self.layout[i][j] = SeatStatus::Empty;
-                        }
-                    }
-                    SeatStatus::Floor => (),
-                }
-            }
+pub struct Seats {
+    layout: Grid<SeatStatus>,
+    // step1/step2 keep stepping the same automaton (for part 1 or 2), which
+    // holds the layout in the meantime
+    stepping: Option<(u8, Automaton<SeatStatus, SeatRule>)>,
+}
+
+fn is_seat(s: &SeatStatus) -> bool {
+    s != &SeatStatus::Floor
+}
+
+impl Seats {
+    fn new(layout: Grid<SeatStatus>) -> Self {
+        Seats {
+            layout,
+            stepping: None,
         }
+    }
 
-        changed
-    }
-
-    fn adjacent_occupied(layout: &Vec<Vec<SeatStatus>>, i: usize, j: usize) -> usize {
-        let moves = vec![
-            (-1, -1),
-            (-1, 0),
-            (-1, 1),
-            (0, -1),
-            (0, 1),
-            (1, -1),
-            (1, 0),
-            (1, 1),
-        ];
-        let mut count: usize = 0;
-
-        for m in moves {
-            let new_i = i as i32 + m.0;
-            let new_j = j as i32 + m.1;
-
-            if new_i >= 0
-                && (new_i as usize) < layout.len()
-                && new_j >= 0
-                && (new_j as usize) < layout[0].len()
-                && layout[new_i as usize][new_j as usize] == SeatStatus::Occupied
-            {
-                count += 1;
-            }
+    fn layout(&self) -> &Grid<SeatStatus> {
+        match &self.stepping {
+            Some((_, automaton)) => automaton.grid(),
+            None => &self.layout,
         }
+    }
 
-        count
-    }
-
-    fn direction_occupied(layout: &Vec<Vec<SeatStatus>>, i: usize, j: usize) -> usize {
-        let moves = vec![
-            (-1, -1),
-            (-1, 0),
-            (-1, 1),
-            (0, -1),
-            (0, 1),
-            (1, -1),
-            (1, 0),
-            (1, 1),
-        ];
-        let mut count: usize = 0;
-
-        for m in moves {
-            let mut new_i = i as i32 + m.0;
-            let mut new_j = j as i32 + m.1;
-
-            loop {
-                if new_i < 0
-                    || (new_i as usize) >= layout.len()
-                    || new_j < 0
-                    || (new_j as usize) >= layout[0].len()
-                {
-                    break;
-                } else if layout[new_i as usize][new_j as usize] == SeatStatus::Occupied {
-                    count += 1;
-                    break;
-                } else if layout[new_i as usize][new_j as usize] == SeatStatus::Empty {
-                    break;
-                }
+    fn into_layout(self) -> Grid<SeatStatus> {
+        match self.stepping {
+            Some((_, automaton)) => automaton.into_grid(),
+            None => self.layout,
+        }
+    }
 
-                new_i += m.0;
-                new_j += m.1;
-            }
+    // The automaton for this part, built the first time it's needed (or when
+    // switching from the other part) so the neighbours are only looked up once
+    fn stepper(&mut self, part: u8) -> &mut Automaton<SeatStatus, SeatRule> {
+        if self.stepping.as_ref().map(|(p, _)| *p) != Some(part) {
+            let layout = match self.stepping.take() {
+                Some((_, automaton)) => automaton.into_grid(),
+                None => std::mem::replace(&mut self.layout, Grid::from_rows(Vec::new())),
+            };
+            let seats = Seats::new(layout);
+            let automaton = match part {
+                1 => seats.into_automaton1(),
+                _ => seats.into_automaton2(),
+            };
+            self.stepping = Some((part, automaton));
         }
 
-        count
+        &mut self.stepping.as_mut().unwrap().1
+    }
+
+    pub fn step1(&mut self) -> bool {
+        self.stepper(1).step()
+    }
+
+    pub fn step2(&mut self) -> bool {
+        self.stepper(2).step()
+    }
+
+    // Same as repeatedly calling step1/step2, but the neighbours of each seat
+    // are only looked up once (floor never changes, so sight lines don't either)
+    pub fn into_automaton1(self) -> Automaton<SeatStatus, SeatRule> {
+        Automaton::new(self.into_layout(), Adjacent, SeatRule { tolerance: 4 })
+    }
+
+    pub fn into_automaton2(self) -> Automaton<SeatStatus, SeatRule> {
+        let visible_seats = LineOfSight {
+            is_visible: is_seat,
+        };
+        Automaton::new(self.into_layout(), visible_seats, SeatRule { tolerance: 5 })
+    }
+
+    pub fn generations1(self) -> Generations<SeatStatus, SeatRule> {
+        self.into_automaton1().generations()
+    }
+
+    pub fn generations2(self) -> Generations<SeatStatus, SeatRule> {
+        self.into_automaton2().generations()
     }
 
     pub fn count_occupied(&self) -> usize {
-        let mut count = 0;
-        for row in self.layout.iter() {
-            for seat in row.iter() {
-                if seat == &SeatStatus::Occupied {
-                    count += 1;
-                }
+        self.layout().count(|s| s == &SeatStatus::Occupied)
+    }
+}
+
+impl PartialEq for Seats {
+    fn eq(&self, other: &Self) -> bool {
+        self.layout() == other.layout()
+    }
+}
+
+impl fmt::Debug for Seats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Seats")
+            .field("layout", self.layout())
+            .finish()
+    }
+}
+
+impl fmt::Display for Seats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.layout().to_text(SeatStatus::symbol))
+    }
+}
+
+pub enum Render {
+    // Every generation one after the other
+    Text,
+    // Redraws the layout in place, waiting a bit between generations
+    Terminal(Duration),
+    // One gen_NNNN.ppm file per generation, seats drawn as scale x scale squares
+    Ppm { directory: PathBuf, scale: usize },
+}
+
+// Draws every generation and then a summary of how many seats changed in each
+// one. Returns those counts, the first generation not being included since
+// nothing changed to get there. Seats still changing after max_generations
+// are an error wrapping BudgetExceeded, as they might never stop.
+pub fn render<W: Write>(
+    generations: Generations<SeatStatus, SeatRule>,
+    mode: &Render,
+    max_generations: usize,
+    out: &mut W,
+) -> io::Result<Vec<usize>> {
+    if let Render::Ppm { directory, .. } = mode {
+        fs::create_dir_all(directory)?;
+    }
+
+    let mut changes = Vec::new();
+    for generation in generations {
+        if generation.number > max_generations {
+            return Err(io::Error::other(BudgetExceeded {
+                generations: max_generations,
+            }));
+        }
+        if generation.number > 0 {
+            changes.push(generation.changed);
+        }
+
+        let text = generation.grid.to_text(SeatStatus::symbol);
+        match mode {
+            Render::Text => {
+                writeln!(out, "Generation {}:\n{}", generation.number, text)?;
+            }
+            Render::Terminal(delay) => {
+                // Clear the screen and go back to the top left corner
+                write!(out, "\x1b[2J\x1b[H")?;
+                writeln!(out, "Generation {}:\n{}", generation.number, text)?;
+                out.flush()?;
+                thread::sleep(*delay);
+            }
+            Render::Ppm { directory, scale } => {
+                let path = directory.join(format!("gen_{:04}.ppm", generation.number));
+                let mut file = BufWriter::new(File::create(path)?);
+                generation
+                    .grid
+                    .write_ppm(&mut file, *scale, SeatStatus::colour)?;
             }
         }
+    }
 
-        count
+    writeln!(out, "Stabilized after {} generations", changes.len())?;
+    for (i, changed) in changes.iter().enumerate() {
+        writeln!(out, "Generation {}: {} seats changed", i + 1, changed)?;
     }
+
+    Ok(changes)
 }
 
 pub fn input_generator(input: &str) -> Seats {
@@ -166,27 +243,32 @@ pub fn input_generator(input: &str) -> Seats {
         })
         .collect();
 
-    Seats { layout }
+    Seats::new(Grid::from_rows(layout))
 }
 
-#[aoc(day11, part1)]
-pub fn part1(input: &str) -> usize {
-    let mut seats = input_generator(input);
-    while seats.step1() {
-        continue;
+// Way more than any real layout needs, but stops a rule set that never
+// settles from hanging forever
+pub const MAX_GENERATIONS: usize = 10_000;
+
+fn settled_occupied(mut seats: Automaton<SeatStatus, SeatRule>) -> usize {
+    match seats.run_until_settled(MAX_GENERATIONS) {
+        Ok(Outcome::Stable { .. }) => seats.grid().count(|s| s == &SeatStatus::Occupied),
+        Ok(Outcome::Cycle { start, length }) => panic!(
+            "Seats go around in a cycle of {} generations from generation {}!",
+            length, start
+        ),
+        Err(e) => panic!("{}", e),
     }
+}
 
-    seats.count_occupied()
+#[aoc(day11, part1)]
+pub fn part1(input: &str) -> usize {
+    settled_occupied(input_generator(input).into_automaton1())
 }
 
 #[aoc(day11, part2)]
 pub fn part2(input: &str) -> usize {
-    let mut seats = input_generator(input);
-    while seats.step2() {
-        continue;
-    }
-
-    seats.count_occupied()
+    settled_occupied(input_generator(input).into_automaton2())
 }
 
 #[cfg(test)]
@@ -197,12 +279,10 @@ mod tests {
     fn test_input() {
         assert_eq!(
             input_generator("#L.\nLL#"),
-            Seats {
-                layout: vec![
-                    vec![SeatStatus::Occupied, SeatStatus::Empty, SeatStatus::Floor],
-                    vec![SeatStatus::Empty, SeatStatus::Empty, SeatStatus::Occupied]
-                ]
-            }
+            Seats::new(Grid::from_rows(vec![
+                vec![SeatStatus::Occupied, SeatStatus::Empty, SeatStatus::Floor],
+                vec![SeatStatus::Empty, SeatStatus::Empty, SeatStatus::Occupied]
+            ]))
         );
     }
 
@@ -221,4 +301,103 @@ mod tests {
             26
         );
     }
+
+    #[test]
+    fn test_steps() {
+        let layout = "L.LL.LL.LL\nLLLLLLL.LL\nL.L.L..L..\nLLLL.LL.LL\nL.LL.LL.LL\nL.LLLLL.LL\n..L.L.....\nLLLLLLLLLL\nL.LLLLLL.L\nL.LLLLL.LL";
+
+        let mut seats = input_generator(layout);
+        while seats.step1() {}
+        assert_eq!(seats.count_occupied(), 37);
+
+        let mut seats = input_generator(layout);
+        while seats.step2() {}
+        assert_eq!(seats.count_occupied(), 26);
+
+        // Switching rules halfway carries on from where the other left off
+        let mut seats = input_generator(layout);
+        assert!(seats.step1());
+        assert_eq!(seats.count_occupied(), 71);
+        assert!(seats.step2());
+        let mut expected = input_generator(layout);
+        expected.step2();
+        expected.step2();
+        assert_eq!(seats, expected);
+        assert_eq!(input_generator(layout).into_automaton2().run(), 6);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut out = Vec::new();
+        let changes = render(
+            input_generator("LL\nL.").generations1(),
+            &Render::Text,
+            MAX_GENERATIONS,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(changes, vec![3]);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Generation 0:\nLL\nL.\n\nGeneration 1:\n##\n#.\n\n\
+             Stabilized after 1 generations\nGeneration 1: 3 seats changed\n"
+        );
+
+        // Unique to this run, and emptied first in case an aborted run with
+        // the same pid left something behind
+        let directory =
+            std::env::temp_dir().join(format!("aoc2020_day11_render_{}", std::process::id()));
+        if directory.exists() {
+            fs::remove_dir_all(&directory).unwrap();
+        }
+        let render_ppm = Render::Ppm {
+            directory: directory.clone(),
+            scale: 1,
+        };
+        let changes = render(
+            input_generator("L.LL.LL.LL\nLLLLLLL.LL\nL.L.L..L..\nLLLL.LL.LL\nL.LL.LL.LL\nL.LLLLL.LL\n..L.L.....\nLLLLLLLLLL\nL.LLLLLL.L\nL.LLLLL.LL").generations2(),
+            &render_ppm,
+            MAX_GENERATIONS,
+            &mut io::sink(),
+        )
+        .unwrap();
+
+        let last = directory.join("gen_0006.ppm").exists();
+        let past_last = directory.join("gen_0007.ppm").exists();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(changes.len(), 6);
+        assert!(last);
+        assert!(!past_last);
+    }
+
+    #[test]
+    fn test_oscillating_seats() {
+        // Nobody tolerates a single neighbour, so two seats side by side keep
+        // getting taken and left together
+        let seats = input_generator("LL");
+        let mut automaton =
+            Automaton::new(seats.into_layout(), Adjacent, SeatRule { tolerance: 1 });
+        assert_eq!(
+            automaton.run_until_settled(MAX_GENERATIONS),
+            Ok(Outcome::Cycle {
+                start: 0,
+                length: 2
+            })
+        );
+
+        let generations = Automaton::new(
+            input_generator("LL").into_layout(),
+            Adjacent,
+            SeatRule { tolerance: 1 },
+        )
+        .generations();
+        let mut out = Vec::new();
+        let error = render(generations, &Render::Text, 5, &mut out).unwrap_err();
+        assert_eq!(error.to_string(), "Still changing after 5 generations");
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("Generation 5:\n##\n\n"));
+    }
 }
@@ -1,4 +1,8 @@
-#[derive(Debug, PartialEq)]
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Direction {
     North,
     South,
@@ -8,244 +12,429 @@ pub enum Direction {
     Right,
     Forward,
 }
+
+impl Direction {
+    // Only compass directions point somewhere
+    pub fn unit(&self) -> Option<Vector> {
+        match self {
+            Direction::North => Some(Vector::new(1, 0)),
+            Direction::South => Some(Vector::new(-1, 0)),
+            Direction::East => Some(Vector::new(0, 1)),
+            Direction::West => Some(Vector::new(0, -1)),
+            _ => None,
+        }
+    }
+}
+
+// What positions can be stored in. i32 is plenty for the puzzle, i64 and i128
+// are there for instruction lists that go a lot further.
+pub trait Coordinate:
+    Copy
+    + Ord
+    + fmt::Debug
+    + fmt::Display
+    + From<i32>
+    + Into<i128>
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Neg<Output = Self>
+{
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn checked_mul(self, other: Self) -> Option<Self>;
+    fn checked_neg(self) -> Option<Self>;
+}
+
+macro_rules! impl_coordinate {
+    ($($t:ty),*) => {
+        $(
+            impl Coordinate for $t {
+                fn checked_add(self, other: Self) -> Option<Self> {
+                    <$t>::checked_add(self, other)
+                }
+
+                fn checked_mul(self, other: Self) -> Option<Self> {
+                    <$t>::checked_mul(self, other)
+                }
+
+                fn checked_neg(self) -> Option<Self> {
+                    <$t>::checked_neg(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_coordinate!(i32, i64, i128);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Vector<T = i32> {
+    pub north: T,
+    pub east: T,
+}
+
+impl<T: Coordinate> Vector<T> {
+    pub fn new(north: T, east: T) -> Self {
+        Vector { north, east }
+    }
+
+    pub fn cast<U: Coordinate + From<T>>(self) -> Vector<U> {
+        Vector::new(U::from(self.north), U::from(self.east))
+    }
+
+    fn wide(self) -> Vector<i128> {
+        Vector::new(self.north.into(), self.east.into())
+    }
+
+    pub fn manhattan(&self) -> u128 {
+        let north: i128 = self.north.into();
+        let east: i128 = self.east.into();
+        north.unsigned_abs().saturating_add(east.unsigned_abs())
+    }
+
+    // Counter-clockwise, negative degrees turn right. Anything that is a
+    // multiple of 90 works, no matter how many full turns it has. None for
+    // any other angle, or if a component is T::MIN and has to be negated.
+    pub fn rotated_left(self, degrees: i32) -> Option<Self> {
+        if degrees % 90 != 0 {
+            return None;
+        }
+
+        Some(match (degrees / 90).rem_euclid(4) {
+            0 => self,
+            1 => Vector::new(self.east, self.north.checked_neg()?),
+            2 => Vector::new(self.north.checked_neg()?, self.east.checked_neg()?),
+            _ => Vector::new(self.east.checked_neg()?, self.north),
+        })
+    }
+
+    pub fn rotated_right(self, degrees: i32) -> Option<Self> {
+        self.rotated_left(-degrees)
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        Some(Vector::new(
+            self.north.checked_add(other.north)?,
+            self.east.checked_add(other.east)?,
+        ))
+    }
+
+    pub fn checked_mul(self, factor: T) -> Option<Self> {
+        Some(Vector::new(
+            self.north.checked_mul(factor)?,
+            self.east.checked_mul(factor)?,
+        ))
+    }
+}
+
+impl<T: Coordinate> Add for Vector<T> {
+    type Output = Vector<T>;
+
+    fn add(self, other: Vector<T>) -> Vector<T> {
+        Vector::new(self.north + other.north, self.east + other.east)
+    }
+}
+
+impl<T: Coordinate> Sub for Vector<T> {
+    type Output = Vector<T>;
+
+    fn sub(self, other: Vector<T>) -> Vector<T> {
+        Vector::new(self.north - other.north, self.east - other.east)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum NavigationError {
+    // The position or waypoint no longer fits in the coordinate type
+    Overflow { instruction: usize },
+    // Turns have to be multiples of 90 degrees
+    InvalidAngle { instruction: usize },
+}
+
+impl fmt::Display for NavigationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NavigationError::Overflow { instruction } => {
+                write!(f, "Coordinates overflowed at instruction {}", instruction)
+            }
+            NavigationError::InvalidAngle { instruction } => write!(
+                f,
+                "Can only turn in multiples of 90 degrees, instruction {} doesn't",
+                instruction
+            ),
+        }
+    }
+}
+
+// South-west and north-east corners of a non-empty list of points
+fn bounds<T: Coordinate>(points: &[Vector<T>]) -> (Vector<T>, Vector<T>) {
+    let first = points[0];
+    points.iter().fold((first, first), |(min, max), p| {
+        (
+            Vector::new(min.north.min(p.north), min.east.min(p.east)),
+            Vector::new(max.north.max(p.north), max.east.max(p.east)),
+        )
+    })
+}
+
+// Where the ship was after each instruction (starting point included), and in
+// waypoint mode also where the waypoint was at the same moments. Waypoints are
+// kept relative to the ship, same as the ship sees them, so that recording
+// them can't overflow before the ship itself does.
+#[derive(Debug, PartialEq)]
+pub struct Route<T = i32> {
+    pub positions: Vec<Vector<T>>,
+    pub waypoints: Vec<Vector<T>>,
+}
+
+impl<T: Coordinate> Route<T> {
+    fn new(start: Vector<T>) -> Self {
+        Route {
+            positions: vec![start],
+            waypoints: Vec::new(),
+        }
+    }
+
+    // South-west and north-east corners of what the ship covered
+    pub fn bounding_box(&self) -> (Vector<T>, Vector<T>) {
+        bounds(&self.positions)
+    }
+
+    pub fn distance_travelled(&self) -> u128 {
+        self.positions
+            .windows(2)
+            .map(|pair| (pair[1].wide() - pair[0].wide()).manhattan())
+            .sum()
+    }
+
+    // Furthest the ship ever got from where it started
+    pub fn farthest_point(&self) -> Vector<T> {
+        let start = self.positions[0].wide();
+        *self
+            .positions
+            .iter()
+            .max_by_key(|p| (p.wide() - start).manhattan())
+            .unwrap()
+    }
+
+    // Where the waypoints actually were, rather than relative to the ship
+    pub fn absolute_waypoints(&self) -> Vec<Vector<i128>> {
+        self.positions
+            .iter()
+            .zip(self.waypoints.iter())
+            .map(|(p, w)| p.wide() + w.wide())
+            .collect()
+    }
+
+    // North is up, so the y axis gets flipped
+    pub fn to_svg(&self) -> String {
+        let positions: Vec<Vector<i128>> = self.positions.iter().map(|p| p.wide()).collect();
+        let waypoints = self.absolute_waypoints();
+        let everything: Vec<Vector<i128>> =
+            positions.iter().chain(waypoints.iter()).copied().collect();
+        let (min, max) = bounds(&everything);
+        let margin = 1.max((max.east - min.east).max(max.north - min.north) / 20);
+        let points = |track: &[Vector<i128>]| {
+            track
+                .iter()
+                .map(|p| format!("{},{}", p.east, -p.north))
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            min.east - margin,
+            -max.north - margin,
+            max.east - min.east + 2 * margin,
+            max.north - min.north + 2 * margin
+        );
+        svg += &format!(
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"navy\" stroke-width=\"{}\"/>\n",
+            points(&positions),
+            margin / 4 + 1
+        );
+        if !self.waypoints.is_empty() {
+            svg += &format!(
+                "  <polyline points=\"{}\" fill=\"none\" stroke=\"orange\" stroke-width=\"{}\" stroke-dasharray=\"{}\"/>\n",
+                points(&waypoints),
+                margin / 4 + 1,
+                margin
+            );
+        }
+        svg += "</svg>\n";
+
+        svg
+    }
+
+    // Coordinates go [east, north], as GeoJSON wants x before y
+    pub fn to_geojson(&self) -> String {
+        let feature = |name: &str, track: &[Vector<i128>]| {
+            let coordinates: Vec<String> = track
+                .iter()
+                .map(|p| format!("[{},{}]", p.east, p.north))
+                .collect();
+            format!(
+                "{{\"type\":\"Feature\",\"properties\":{{\"name\":\"{}\"}},\"geometry\":{{\"type\":\"LineString\",\"coordinates\":[{}]}}}}",
+                name,
+                coordinates.join(",")
+            )
+        };
+
+        let positions: Vec<Vector<i128>> = self.positions.iter().map(|p| p.wide()).collect();
+        let mut features = vec![feature("ship", &positions)];
+        if !self.waypoints.is_empty() {
+            features.push(feature("waypoint", &self.absolute_waypoints()));
+        }
+
+        format!(
+            "{{\"type\":\"FeatureCollection\",\"features\":[{}]}}",
+            features.join(",")
+        )
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Instruction {
     direction: Direction,
     units: i32,
 }
+
+impl Instruction {
+    pub fn new(direction: Direction, units: i32) -> Self {
+        Instruction { direction, units }
+    }
+
+    fn is_turn(&self) -> bool {
+        self.direction == Direction::Left || self.direction == Direction::Right
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.direction {
+            Direction::North => 'N',
+            Direction::South => 'S',
+            Direction::East => 'E',
+            Direction::West => 'W',
+            Direction::Left => 'L',
+            Direction::Right => 'R',
+            Direction::Forward => 'F',
+        };
+        write!(f, "{}{}", action, self.units)
+    }
+}
+
 #[derive(Debug, PartialEq)]
-pub struct Ship {
+pub struct Ship<T = i32> {
     instructions: Vec<Instruction>,
-    curr_direction: Direction,
-    curr_position: (i32, i32), //north-south, east-west
-    waypoint: (i32, i32),
-    waypoint_directions: (Direction, Direction),
+    heading: Vector<T>,
+    curr_position: Vector<T>,
+    waypoint: Vector<T>,
 }
 
 impl Ship {
-    pub fn process_instructions(&mut self) {
-        for i in self.instructions.iter() {
-            match i.direction {
-                Direction::North => self.curr_position.0 += i.units,
-                Direction::South => self.curr_position.0 -= i.units,
-                Direction::East => self.curr_position.1 += i.units,
-                Direction::West => self.curr_position.1 -= i.units,
-                Direction::Left => {
-                    self.curr_direction =
-                        Ship::change_direction(&self.curr_direction, Direction::Left, i.units)
-                }
-                Direction::Right => {
-                    self.curr_direction =
-                        Ship::change_direction(&self.curr_direction, Direction::Right, i.units)
-                }
-                Direction::Forward => match self.curr_direction {
-                    Direction::North => self.curr_position.0 += i.units,
-                    Direction::South => self.curr_position.0 -= i.units,
-                    Direction::East => self.curr_position.1 += i.units,
-                    Direction::West => self.curr_position.1 -= i.units,
-                    _ => panic!("Invalid current direction"),
-                },
-            }
+    // At the origin facing east, with the waypoint 10 east and 1 north
+    pub fn new(instructions: Vec<Instruction>) -> Self {
+        Ship {
+            instructions,
+            heading: Vector::new(0, 1),
+            curr_position: Vector::new(0, 0),
+            waypoint: Vector::new(1, 10),
         }
     }
 
-    pub fn change_direction(
-        original_direction: &Direction,
-        turn_direction: Direction,
-        units: i32,
-    ) -> Direction {
-        match turn_direction {
-            Direction::Left => {
-                return Ship::turn_left(original_direction, units);
-            }
-            Direction::Right => {
-                return Ship::turn_right(original_direction, units);
-            }
-            _ => panic!("Only L and R change directions!"),
+    // Same ship, but tracking its position with a bigger integer type
+    pub fn widen<T: Coordinate>(self) -> Ship<T> {
+        Ship {
+            instructions: self.instructions,
+            heading: self.heading.cast(),
+            curr_position: self.curr_position.cast(),
+            waypoint: self.waypoint.cast(),
         }
     }
+}
 
-    pub fn process_instructions2(&mut self) {
-        for i in self.instructions.iter() {
+impl<T: Coordinate> Ship<T> {
+    pub fn process_instructions(&mut self) -> Result<Route<T>, NavigationError> {
+        let mut route = Route::new(self.curr_position);
+        for (n, i) in self.instructions.iter().enumerate() {
+            let overflow = NavigationError::Overflow { instruction: n };
+            let units = T::from(i.units);
+            if i.is_turn() && i.units % 90 != 0 {
+                return Err(NavigationError::InvalidAngle { instruction: n });
+            }
             match i.direction {
-                Direction::North => {
-                    match self.waypoint_directions.0 {
-                        Direction::North => self.waypoint.0 += i.units,
-                        Direction::South => self.waypoint.0 -= i.units,
-                        _ => (),
-                    }
-                    match self.waypoint_directions.1 {
-                        Direction::North => self.waypoint.1 += i.units,
-                        Direction::South => self.waypoint.1 -= i.units,
-                        _ => (),
-                    }
-                }
-                Direction::South => {
-                    match self.waypoint_directions.0 {
-                        Direction::North => self.waypoint.0 -= i.units,
-                        Direction::South => self.waypoint.0 += i.units,
-                        _ => (),
-                    }
-                    match self.waypoint_directions.1 {
-                        Direction::North => self.waypoint.1 -= i.units,
-                        Direction::South => self.waypoint.1 += i.units,
-                        _ => (),
-                    }
-                }
-                Direction::East => {
-                    match self.waypoint_directions.0 {
-                        Direction::East => self.waypoint.0 += i.units,
-                        Direction::West => self.waypoint.0 -= i.units,
-                        _ => (),
-                    }
-                    match self.waypoint_directions.1 {
-                        Direction::East => self.waypoint.1 += i.units,
-                        Direction::West => self.waypoint.1 -= i.units,
-                        _ => (),
-                    }
-                }
-                Direction::West => {
-                    match self.waypoint_directions.0 {
-                        Direction::East => self.waypoint.0 -= i.units,
-                        Direction::West => self.waypoint.0 += i.units,
-                        _ => (),
-                    }
-                    match self.waypoint_directions.1 {
-                        Direction::East => self.waypoint.1 -= i.units,
-                        Direction::West => self.waypoint.1 += i.units,
-                        _ => (),
-                    }
-                }
                 Direction::Left => {
-                    self.waypoint_directions.0 = Ship::change_direction(
-                        &self.waypoint_directions.0,
-                        Direction::Left,
-                        i.units,
-                    );
-                    self.waypoint_directions.1 = Ship::change_direction(
-                        &self.waypoint_directions.1,
-                        Direction::Left,
-                        i.units,
-                    );
+                    self.heading = self.heading.rotated_left(i.units).ok_or(overflow)?
                 }
                 Direction::Right => {
-                    self.waypoint_directions.0 = Ship::change_direction(
-                        &self.waypoint_directions.0,
-                        Direction::Right,
-                        i.units,
-                    );
-                    self.waypoint_directions.1 = Ship::change_direction(
-                        &self.waypoint_directions.1,
-                        Direction::Right,
-                        i.units,
-                    );
+                    self.heading = self.heading.rotated_right(i.units).ok_or(overflow)?
                 }
                 Direction::Forward => {
-                    for _ in 0..i.units {
-                        match self.waypoint_directions.0 {
-                            Direction::North => self.curr_position.0 += self.waypoint.0,
-                            Direction::South => self.curr_position.0 -= self.waypoint.0,
-                            Direction::East => self.curr_position.1 += self.waypoint.0,
-                            Direction::West => self.curr_position.1 -= self.waypoint.0,
-                            _ => panic!("Waypoint direction 0 should be in {N, S, E, W}"),
-                        }
-                        match self.waypoint_directions.1 {
-                            Direction::North => self.curr_position.0 += self.waypoint.1,
-                            Direction::South => self.curr_position.0 -= self.waypoint.1,
-                            Direction::East => self.curr_position.1 += self.waypoint.1,
-                            Direction::West => self.curr_position.1 -= self.waypoint.1,
-                            _ => panic!("Waypoint direction 1 should be in {N, S, E, W}"),
-                        }
-                    }
+                    self.curr_position = self
+                        .heading
+                        .checked_mul(units)
+                        .and_then(|v| self.curr_position.checked_add(v))
+                        .ok_or(overflow)?;
+                }
+                ref compass => {
+                    self.curr_position = compass
+                        .unit()
+                        .unwrap()
+                        .cast()
+                        .checked_mul(units)
+                        .and_then(|v| self.curr_position.checked_add(v))
+                        .ok_or(overflow)?;
                 }
             }
+            route.positions.push(self.curr_position);
         }
+
+        Ok(route)
     }
 
-    fn turn_left(original_direction: &Direction, units: i32) -> Direction {
-        match original_direction {
-            Direction::North => {
-                if units == 90 {
-                    return Direction::West;
-                } else if units == 180 {
-                    return Direction::South;
-                } else if units == 270 {
-                    return Direction::East;
-                }
-            }
-            Direction::South => {
-                if units == 90 {
-                    return Direction::East;
-                } else if units == 180 {
-                    return Direction::North;
-                } else if units == 270 {
-                    return Direction::West;
-                }
-            }
-            Direction::East => {
-                if units == 90 {
-                    return Direction::North;
-                } else if units == 180 {
-                    return Direction::West;
-                } else if units == 270 {
-                    return Direction::South;
-                }
-            }
-            Direction::West => {
-                if units == 90 {
-                    return Direction::South;
-                } else if units == 180 {
-                    return Direction::East;
-                } else if units == 270 {
-                    return Direction::North;
-                }
+    pub fn process_instructions2(&mut self) -> Result<Route<T>, NavigationError> {
+        let mut route = Route::new(self.curr_position);
+        route.waypoints.push(self.waypoint);
+
+        for (n, i) in self.instructions.iter().enumerate() {
+            let overflow = NavigationError::Overflow { instruction: n };
+            let units = T::from(i.units);
+            if i.is_turn() && i.units % 90 != 0 {
+                return Err(NavigationError::InvalidAngle { instruction: n });
             }
-            _ => panic!("curr_direction needs to be one of {N, S, E, W}"),
-        }
-        panic!("Turn left failed");
-    }
-
-    fn turn_right(original_direction: &Direction, units: i32) -> Direction {
-        match original_direction {
-            Direction::North => {
-                if units == 90 {
-                    return Direction::East;
-                } else if units == 180 {
-                    return Direction::South;
-                } else if units == 270 {
-                    return Direction::West;
+            match i.direction {
+                Direction::Left => {
+                    self.waypoint = self.waypoint.rotated_left(i.units).ok_or(overflow)?
                 }
-            }
-            Direction::South => {
-                if units == 90 {
-                    return Direction::West;
-                } else if units == 180 {
-                    return Direction::North;
-                } else if units == 270 {
-                    return Direction::East;
+                Direction::Right => {
+                    self.waypoint = self.waypoint.rotated_right(i.units).ok_or(overflow)?
                 }
-            }
-            Direction::East => {
-                if units == 90 {
-                    return Direction::South;
-                } else if units == 180 {
-                    return Direction::West;
-                } else if units == 270 {
-                    return Direction::North;
+                // Moving to the waypoint `units` times is the same as moving
+                // by `units` times the waypoint all at once
+                Direction::Forward => {
+                    self.curr_position = self
+                        .waypoint
+                        .checked_mul(units)
+                        .and_then(|v| self.curr_position.checked_add(v))
+                        .ok_or(overflow)?;
                 }
-            }
-            Direction::West => {
-                if units == 90 {
-                    return Direction::North;
-                } else if units == 180 {
-                    return Direction::East;
-                } else if units == 270 {
-                    return Direction::South;
+                ref compass => {
+                    self.waypoint = compass
+                        .unit()
+                        .unwrap()
+                        .cast()
+                        .checked_mul(units)
+                        .and_then(|v| self.waypoint.checked_add(v))
+                        .ok_or(overflow)?;
                 }
             }
-            _ => panic!("curr_direction needs to be one of {N, S, E, W}"),
+            route.positions.push(self.curr_position);
+            route.waypoints.push(self.waypoint);
         }
-        panic!("Turn left failed");
+
+        Ok(route)
     }
 }
 
@@ -283,31 +472,237 @@ pub fn input_generator(input: &str) -> Ship {
             },
             _ => panic!("Invalid input!"),
         })
+        .inspect(|i: &Instruction| {
+            if i.is_turn() && i.units % 90 != 0 {
+                panic!("Can only turn in multiples of 90 degrees, got {}", i);
+            }
+        })
         .collect();
 
-    Ship {
-        instructions,
-        curr_direction: Direction::East,
-        curr_position: (0, 0),
-        waypoint: (1, 10),
-        waypoint_directions: (Direction::North, Direction::East),
+    Ship::new(instructions)
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    // N/S/E/W move the ship, as in part 1
+    Direct,
+    // N/S/E/W move the waypoint, as in part 2
+    Waypoint,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlanError {
+    // Headings have to be one of the four compass unit vectors
+    InvalidHeading,
+    // In waypoint mode L and R turn the waypoint, so the ship always faces east
+    UnreachableHeading,
+    // Some instruction would need more units than fit in an i32
+    TooFar,
+}
+
+fn compass_move(
+    delta: i64,
+    positive: Direction,
+    negative: Direction,
+) -> Result<Option<Instruction>, PlanError> {
+    let units = i32::try_from(delta.abs()).map_err(|_| PlanError::TooFar)?;
+    Ok(match delta {
+        0 => None,
+        d if d > 0 => Some(Instruction::new(positive, units)),
+        _ => Some(Instruction::new(negative, units)),
+    })
+}
+
+// Like compass_move, but split into as many instructions as it takes for each
+// one to fit in an i32
+fn compass_moves(delta: i64, positive: Direction, negative: Direction) -> Vec<Instruction> {
+    let direction = |units| {
+        if delta > 0 {
+            Instruction::new(positive, units)
+        } else {
+            Instruction::new(negative, units)
+        }
+    };
+    let mut left = delta.abs();
+    let mut moves = Vec::new();
+    while left > 0 {
+        let units = left.min(i64::from(i32::MAX));
+        moves.push(direction(units as i32));
+        left -= units;
+    }
+
+    moves
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Smallest divisor above 1, or 1 for 1
+fn smallest_factor(n: i64) -> i64 {
+    (2..)
+        .take_while(|d| d * d <= n)
+        .find(|d| n % d == 0)
+        .unwrap_or(n)
+}
+
+fn forward(times: i64) -> Result<Instruction, PlanError> {
+    let units = i32::try_from(times).map_err(|_| PlanError::TooFar)?;
+    Ok(Instruction::new(Direction::Forward, units))
+}
+
+// k >= 1 such that target = k * step
+fn positive_multiple(target: (i64, i64), step: (i64, i64)) -> Option<i64> {
+    let k = if step.0 != 0 {
+        target.0 / step.0
+    } else if step.1 != 0 {
+        target.1 / step.1
+    } else {
+        return None;
+    };
+
+    if k >= 1 && k * step.0 == target.0 && k * step.1 == target.1 {
+        Some(k)
+    } else {
+        None
     }
 }
 
+// Fewest instructions that take a ship fresh out of Ship::new to the target
+// position, facing the given heading.
+//
+// Directly, every instruction changes at most one of north, east and heading,
+// so it's one move per axis that isn't already right plus one turn if needed.
+// A move that doesn't fit in an i32 takes two.
+//
+// With a waypoint the ship only moves on F, by a multiple of the waypoint. So
+// it's nothing, a single F if the target is a multiple of the starting
+// waypoint, two instructions if one turn or one compass move gets the
+// waypoint to something the target is a multiple of, and otherwise setting
+// the waypoint to the target and going there once always takes three. When
+// the waypoint can't get to the target in a single move per axis, setting it
+// to target / k and going forward k times does for any common divisor k > 1,
+// and if there isn't one the moves get split up.
+pub fn plan_route(
+    target: Vector,
+    heading: Vector,
+    mode: Mode,
+) -> Result<Vec<Instruction>, PlanError> {
+    let start = Ship::new(Vec::new());
+    let turns = (0..4)
+        .find(|&q| start.heading.rotated_right(90 * q) == Some(heading))
+        .ok_or(PlanError::InvalidHeading)?;
+    let (n, e) = (i64::from(target.north), i64::from(target.east));
+
+    match mode {
+        Mode::Direct => {
+            let mut plan = Vec::new();
+            plan.extend(compass_moves(n, Direction::North, Direction::South));
+            plan.extend(compass_moves(e, Direction::East, Direction::West));
+            match turns {
+                0 => (),
+                3 => plan.push(Instruction::new(Direction::Left, 90)),
+                q => plan.push(Instruction::new(Direction::Right, 90 * q)),
+            }
+
+            Ok(plan)
+        }
+        Mode::Waypoint => {
+            if turns != 0 {
+                return Err(PlanError::UnreachableHeading);
+            }
+            if (n, e) == (0, 0) {
+                return Ok(Vec::new());
+            }
+
+            let w = start.waypoint;
+            let (wn, we) = (i64::from(w.north), i64::from(w.east));
+            if let Some(k) = positive_multiple((n, e), (wn, we)) {
+                return Ok(vec![forward(k)?]);
+            }
+
+            for q in 1..4 {
+                // The starting waypoint is small, turning it can't overflow
+                let turned = w.rotated_right(90 * q).unwrap();
+                let step = (i64::from(turned.north), i64::from(turned.east));
+                if let Some(k) = positive_multiple((n, e), step) {
+                    return Ok(vec![
+                        Instruction::new(Direction::Right, 90 * q),
+                        forward(k)?,
+                    ]);
+                }
+            }
+
+            // Only the north part of the waypoint changes, so the east part
+            // decides how many times we go forward
+            if we != 0 && e % we == 0 && e / we >= 1 && n % (e / we) == 0 {
+                let k = e / we;
+                let adjust = compass_move(n / k - wn, Direction::North, Direction::South);
+                if let (Ok(adjust), Ok(f)) = (adjust, forward(k)) {
+                    return Ok(adjust.into_iter().chain(Some(f)).collect());
+                }
+            }
+
+            // Same thing the other way around
+            if wn != 0 && n % wn == 0 && n / wn >= 1 && e % (n / wn) == 0 {
+                let k = n / wn;
+                let adjust = compass_move(e / k - we, Direction::East, Direction::West);
+                if let (Ok(adjust), Ok(f)) = (adjust, forward(k)) {
+                    return Ok(adjust.into_iter().chain(Some(f)).collect());
+                }
+            }
+
+            let fits = |delta: i64| i32::try_from(delta).is_ok();
+            let k = if fits(n - wn) && fits(e - we) {
+                1
+            } else {
+                smallest_factor(gcd(n, e))
+            };
+            let mut plan = Vec::new();
+            plan.extend(compass_moves(
+                n / k - wn,
+                Direction::North,
+                Direction::South,
+            ));
+            plan.extend(compass_moves(e / k - we, Direction::East, Direction::West));
+            plan.push(forward(k)?);
+
+            Ok(plan)
+        }
+    }
+}
+
+// Replays a plan on a real ship to check it ends up where it should
+pub fn verify_route(plan: Vec<Instruction>, target: Vector, heading: Vector, mode: Mode) -> bool {
+    let mut ship = Ship::new(plan);
+    let result = match mode {
+        Mode::Direct => ship.process_instructions(),
+        Mode::Waypoint => ship.process_instructions2(),
+    };
+
+    result.is_ok() && ship.curr_position == target && ship.heading == heading
+}
+
 #[aoc(day12, part1)]
 pub fn part1(input: &str) -> usize {
     let mut ship = input_generator(input);
-    ship.process_instructions();
+    ship.process_instructions()
+        .unwrap_or_else(|e| panic!("{}", e));
 
-    (ship.curr_position.0.abs() + ship.curr_position.1.abs()) as usize
+    ship.curr_position.manhattan() as usize
 }
 
 #[aoc(day12, part2)]
 pub fn part2(input: &str) -> usize {
     let mut ship = input_generator(input);
-    ship.process_instructions2();
+    ship.process_instructions2()
+        .unwrap_or_else(|e| panic!("{}", e));
 
-    (ship.curr_position.0.abs() + ship.curr_position.1.abs()) as usize
+    ship.curr_position.manhattan() as usize
 }
 
 #[cfg(test)]
@@ -329,14 +724,204 @@ mod tests {
                         units: 3
                     }
                 ],
-                curr_direction: Direction::East,
-                curr_position: (0, 0),
-                waypoint: (1, 10),
-                waypoint_directions: (Direction::North, Direction::East),
+                heading: Vector::new(0, 1),
+                curr_position: Vector::new(0, 0),
+                waypoint: Vector::new(1, 10),
             }
         );
     }
 
+    #[test]
+    fn test_rotation() {
+        let v = Vector::new(1, 10);
+        assert_eq!(v.rotated_right(90), Some(Vector::new(-10, 1)));
+        assert_eq!(v.rotated_left(90), Some(Vector::new(10, -1)));
+        assert_eq!(v.rotated_left(180), Some(Vector::new(-1, -10)));
+        assert_eq!(v.rotated_left(-90), v.rotated_right(90));
+        assert_eq!(v.rotated_right(450), v.rotated_right(90));
+        assert_eq!(v.rotated_left(-630), v.rotated_right(270));
+        assert_eq!(v.rotated_left(720), Some(v));
+
+        let v = Vector::new(i32::MIN, 1);
+        assert_eq!(v.rotated_left(90), None);
+        assert_eq!(v.rotated_right(90), Some(Vector::new(-1, i32::MIN)));
+        assert_eq!(v.rotated_left(360), Some(v));
+    }
+
+    #[test]
+    fn test_rotation_not_multiple_of_90() {
+        assert_eq!(Vector::new(0, 1).rotated_left(45), None);
+        assert_eq!(Vector::new(0, 1).rotated_right(-100), None);
+
+        let turns = vec![
+            Instruction::new(Direction::Forward, 1),
+            Instruction::new(Direction::Left, 45),
+        ];
+        assert_eq!(
+            Ship::new(turns).process_instructions(),
+            Err(NavigationError::InvalidAngle { instruction: 1 })
+        );
+        let turns = vec![Instruction::new(Direction::Right, 30)];
+        assert_eq!(
+            Ship::new(turns).process_instructions2(),
+            Err(NavigationError::InvalidAngle { instruction: 0 })
+        );
+    }
+
+    #[test]
+    fn test_route() {
+        let route = input_generator("F10\nN3\nF7\nR90\nF11")
+            .process_instructions()
+            .unwrap();
+        assert_eq!(
+            route.positions,
+            vec![
+                Vector::new(0, 0),
+                Vector::new(0, 10),
+                Vector::new(3, 10),
+                Vector::new(3, 17),
+                Vector::new(3, 17),
+                Vector::new(-8, 17)
+            ]
+        );
+        assert_eq!(route.waypoints, vec![]);
+        assert_eq!(
+            route.bounding_box(),
+            (Vector::new(-8, 0), Vector::new(3, 17))
+        );
+        assert_eq!(route.distance_travelled(), 31);
+        assert_eq!(route.farthest_point(), Vector::new(-8, 17));
+        assert_eq!(
+            route.to_geojson(),
+            "{\"type\":\"FeatureCollection\",\"features\":[{\"type\":\"Feature\",\"properties\":{\"name\":\"ship\"},\"geometry\":{\"type\":\"LineString\",\"coordinates\":[[0,0],[10,0],[10,3],[17,3],[17,3],[17,-8]]}}]}"
+        );
+        assert!(route
+            .to_svg()
+            .contains("points=\"0,0 10,0 10,-3 17,-3 17,-3 17,8\""));
+
+        let route = input_generator("F10\nN3\nF7\nR90\nF11")
+            .process_instructions2()
+            .unwrap();
+        assert_eq!(route.positions.len(), route.waypoints.len());
+        assert_eq!(route.waypoints[0], Vector::new(1, 10));
+        assert_eq!(route.waypoints[2], Vector::new(4, 10));
+        assert_eq!(route.absolute_waypoints()[2], Vector::new(14, 110));
+        assert_eq!(route.farthest_point(), Vector::new(-72, 214));
+        assert_eq!(route.to_svg().matches("<polyline").count(), 2);
+        assert!(route.to_geojson().contains("\"name\":\"waypoint\""));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let far = "F1000000\n".repeat(3000);
+        assert_eq!(
+            input_generator(&far).process_instructions(),
+            Err(NavigationError::Overflow { instruction: 2147 })
+        );
+
+        let mut ship = input_generator(&far).widen::<i64>();
+        let route = ship.process_instructions().unwrap();
+        assert_eq!(route.farthest_point(), Vector::new(0, 3_000_000_000));
+
+        // Turning the waypoint around when it's at i32::MIN
+        let turn = "S2147483647\nS2\nL180";
+        assert_eq!(
+            input_generator(turn).process_instructions2(),
+            Err(NavigationError::Overflow { instruction: 2 })
+        );
+        let mut ship = input_generator(turn).widen::<i64>();
+        ship.process_instructions2().unwrap();
+        assert_eq!(ship.waypoint, Vector::new(2147483648, -10));
+
+        let waypoint = "N1073741823\nF1\nF1";
+        assert_eq!(
+            input_generator(waypoint).process_instructions2(),
+            Err(NavigationError::Overflow { instruction: 2 })
+        );
+        let mut ship = input_generator(waypoint).widen::<i64>();
+        let route = ship.process_instructions2().unwrap();
+        assert_eq!(
+            route.absolute_waypoints()[3],
+            Vector::new(3 * 1_073_741_824, 30)
+        );
+
+        // Each F here has to be done in one go, looping would take forever
+        let huge = "F2000000000\nE1000000000\nF2000000000\nN2000000000\nF2000000000";
+        let mut ship = input_generator(huge).widen::<i128>();
+        ship.process_instructions2().unwrap();
+        assert_eq!(
+            ship.curr_position,
+            Vector::new(4_000_000_006_000_000_000, 4_000_000_060_000_000_000)
+        );
+    }
+
+    #[test]
+    fn test_plan_route() {
+        let south = Vector::new(-1, 0);
+        let east = Vector::new(0, 1);
+
+        let plan = plan_route(Vector::new(-8, 17), south, Mode::Direct).unwrap();
+        let text: Vec<String> = plan.iter().map(|i| i.to_string()).collect();
+        assert_eq!(text, vec!["S8", "E17", "R90"]);
+        assert!(verify_route(plan, Vector::new(-8, 17), south, Mode::Direct));
+        assert_eq!(
+            plan_route(Vector::new(0, 0), east, Mode::Direct),
+            Ok(vec![])
+        );
+
+        let waypoint_plans = vec![
+            (Vector::new(0, 0), vec![]),
+            (Vector::new(5, 50), vec!["F5"]),
+            (Vector::new(-20, 2), vec!["R90", "F2"]),
+            (Vector::new(12, 40), vec!["N2", "F4"]),
+            (Vector::new(3, 21), vec!["W3", "F3"]),
+            (Vector::new(-72, 214), vec!["S73", "E204", "F1"]),
+        ];
+        for (target, expected) in waypoint_plans {
+            let plan = plan_route(target, east, Mode::Waypoint).unwrap();
+            let text: Vec<String> = plan.iter().map(|i| i.to_string()).collect();
+            assert_eq!(text, expected);
+            assert!(verify_route(plan, target, east, Mode::Waypoint));
+        }
+
+        for n in -15..15 {
+            for e in -15..15 {
+                let target = Vector::new(n, e);
+                let plan = plan_route(target, east, Mode::Waypoint).unwrap();
+                assert!(plan.len() <= 3);
+                assert!(verify_route(plan, target, east, Mode::Waypoint));
+            }
+        }
+
+        assert_eq!(
+            plan_route(Vector::new(1, 1), south, Mode::Waypoint),
+            Err(PlanError::UnreachableHeading)
+        );
+        assert_eq!(
+            plan_route(Vector::new(1, 1), Vector::new(1, 1), Mode::Direct),
+            Err(PlanError::InvalidHeading)
+        );
+
+        // As far as an i32 goes
+        let plan = plan_route(Vector::new(i32::MIN, 0), east, Mode::Waypoint).unwrap();
+        let text: Vec<String> = plan.iter().map(|i| i.to_string()).collect();
+        assert_eq!(text, vec!["S1073741825", "W10", "F2"]);
+        let plan = plan_route(Vector::new(i32::MIN, 0), east, Mode::Direct).unwrap();
+        let text: Vec<String> = plan.iter().map(|i| i.to_string()).collect();
+        assert_eq!(text, vec!["S2147483647", "S1"]);
+
+        let extremes = [i32::MIN, i32::MIN + 1, i32::MIN + 9, -1, 0, 1, i32::MAX];
+        for &n in extremes.iter() {
+            for &e in extremes.iter() {
+                let target = Vector::new(n, e);
+                for &mode in [Mode::Direct, Mode::Waypoint].iter() {
+                    let plan = plan_route(target, east, mode).unwrap();
+                    assert!(verify_route(plan, target, east, mode), "{:?}", target);
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_part1() {
         assert_eq!(part1("F10\nN3\nF7\nR90\nF11"), 25);
@@ -1,98 +1,210 @@
-#[aoc_generator(day13)]
-pub fn input_generator(input: &str) -> (usize, Vec<i64>) {
-    let min_timestamp = input.lines().nth(0).unwrap().parse::<usize>().unwrap();
-    let mut bus_lines = Vec::new();
-
-    for l in input.lines().nth(1).unwrap().split(",") {
-        match l {
-            "x" => bus_lines.push(0),
-            _ => bus_lines.push(l.parse::<i64>().unwrap()),
-        }
+use num_bigint::BigInt;
+use std::convert::TryFrom;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bus(pub i64);
+
+impl Bus {
+    // First time the bus leaves at or after the timestamp
+    pub fn next_departure(&self, timestamp: i64) -> i64 {
+        (timestamp + self.0 - 1).div_euclid(self.0) * self.0
     }
+}
 
-    (min_timestamp, bus_lines)
+// Slots are in the order they came in the notes, None being an `x`
+#[derive(Debug, PartialEq)]
+pub struct Schedule {
+    pub earliest: i64,
+    pub slots: Vec<Option<Bus>>,
 }
 
-#[aoc(day13, part1)]
-pub fn part1(input: &(usize, Vec<i64>)) -> i64 {
-    let mut bus_multiples = Vec::new();
-    for b in input.1.iter() {
-        if b < &1 {
-            continue;
-        }
+impl Schedule {
+    pub fn buses(&self) -> impl Iterator<Item = Bus> + '_ {
+        self.slots.iter().filter_map(|&s| s)
+    }
 
-        let mut i = 2;
-        loop {
-            let b_mult = b * i;
-            if b_mult >= input.0 as i64 {
-                bus_multiples.push((b_mult, b.to_owned()));
-            }
-            if b_mult >= 2 * input.0 as i64 {
-                break;
-            }
+    // Every bus with its position in the list, which is how many minutes after
+    // the timestamp it should leave in part 2
+    pub fn offsets(&self) -> Vec<(Bus, i64)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.map(|b| (b, i as i64)))
+            .collect()
+    }
 
-            i += 1;
-        }
+    // The next `count` departures of every bus, starting at the timestamp
+    pub fn departures(&self, timestamp: i64, count: usize) -> Vec<(Bus, Vec<i64>)> {
+        self.buses()
+            .map(|b| {
+                let first = b.next_departure(timestamp);
+                (b, (0..count as i64).map(|i| first + i * b.0).collect())
+            })
+            .collect()
     }
 
-    bus_multiples.sort();
-    for b in bus_multiples.iter() {
-        if b.0 >= input.0 as i64 {
-            return b.1 * (b.0 - input.0 as i64);
+    // One row per minute in [from, to], with a D wherever a bus leaves
+    pub fn timetable(&self, from: i64, to: i64) -> String {
+        let buses: Vec<Bus> = self.buses().collect();
+        let mut table = format!("{:<8}", "time");
+        for b in buses.iter() {
+            table += &format!("{:>8}", format!("bus {}", b.0));
         }
+        table.push('\n');
+
+        for t in from..=to {
+            table += &format!("{:<8}", t);
+            for b in buses.iter() {
+                table += &format!("{:>8}", if t % b.0 == 0 { "D" } else { "." });
+            }
+            table.push('\n');
+        }
+
+        table
     }
+}
+
+// Smallest value of the congruence that is at least `lower`, if it fits an i64
+fn first_at_or_after(c: &Congruence, lower: i64) -> Option<i64> {
+    let lower = BigInt::from(lower);
+    let behind = mod_floor(&(&c.remainder - &lower), &c.modulus);
+    i64::try_from(lower + behind).ok()
+}
+
+// Earliest departures of a and b, both at or after `from`, that are at most
+// `within` minutes apart. For each gap d this means t = 0 (mod a) and
+// t + d = 0 (mod b), so it's a CRT per gap and we keep the earliest.
+pub fn departures_within(a: Bus, b: Bus, within: i64, from: i64) -> Option<(i64, i64)> {
+    (-within..=within)
+        .filter_map(|d| {
+            let solution = crt(&[Congruence::new(0, a.0), Congruence::new(-d, b.0)]).ok()?;
+            // Both have to be at or after `from`
+            let t = first_at_or_after(&solution, from.max(from - d))?;
+            Some((t, t + d))
+        })
+        .min_by_key(|&(t_a, t_b)| (t_a.min(t_b), t_a.max(t_b)))
+}
 
-    panic!("No answer found for part1");
+// First timestamp t where every bus leaves at t + its offset
+pub fn first_matching(pattern: &[(Bus, i64)]) -> Result<BigInt, CrtError> {
+    let congruences: Vec<Congruence> = pattern
+        .iter()
+        .map(|&(b, offset)| Congruence::new(-offset, b.0))
+        .collect();
+
+    crt(&congruences).map(|c| c.remainder)
+}
+
+#[aoc_generator(day13)]
+pub fn input_generator(input: &str) -> Schedule {
+    let mut lines = input.lines();
+    let earliest = lines.next().unwrap().parse::<i64>().unwrap();
+    let slots = lines
+        .next()
+        .unwrap()
+        .split(',')
+        .map(|l| match l {
+            "x" => None,
+            _ => match l.parse::<i64>().unwrap() {
+                // Buses leave every `id` minutes, which takes a positive id
+                id if id > 0 => Some(Bus(id)),
+                id => panic!("Invalid bus id: {}", id),
+            },
+        })
+        .collect();
+
+    Schedule { earliest, slots }
+}
+
+#[aoc(day13, part1)]
+pub fn part1(input: &Schedule) -> i64 {
+    let (bus, departure) = input
+        .buses()
+        .map(|b| (b, b.next_departure(input.earliest)))
+        .min_by_key(|&(_, departure)| departure)
+        .expect("No answer found for part1");
+
+    bus.0 * (departure - input.earliest)
 }
 
 #[aoc(day13, part2)]
-pub fn part2(input: &(usize, Vec<i64>)) -> i64 {
-    let mut divisors = Vec::new();
-    let mut remainders = Vec::new();
-
-    for i in 0..input.1.len() {
-        if input.1[i] != 0 {
-            divisors.push(input.1[i]);
-            remainders.push(input.1[i] - i as i64)
-        }
-    }
+pub fn part2(input: &Schedule) -> i64 {
+    let solution =
+        first_matching(&input.offsets()).unwrap_or_else(|e| panic!("No timestamp works: {:?}", e));
+    i64::try_from(&solution).expect("Timestamp doesn't fit in an i64!")
+}
 
-    crt(divisors, remainders)
+// x = remainder (mod modulus), the remainder kept in [0, modulus) as long as
+// the modulus makes sense (crt complains about the ones that don't)
+#[derive(Debug, PartialEq, Clone)]
+pub struct Congruence {
+    pub remainder: BigInt,
+    pub modulus: BigInt,
 }
 
-// Helpers
-fn crt(divisors: Vec<i64>, remainders: Vec<i64>) -> i64 {
-    let mut product: i64 = 1;
-    for i in 0..divisors.len() {
-        product *= divisors[i];
+impl Congruence {
+    pub fn new(remainder: impl Into<BigInt>, modulus: impl Into<BigInt>) -> Self {
+        let (remainder, modulus) = (remainder.into(), modulus.into());
+        if modulus > BigInt::from(0) {
+            Congruence {
+                remainder: mod_floor(&remainder, &modulus),
+                modulus,
+            }
+        } else {
+            Congruence { remainder, modulus }
+        }
     }
+}
 
-    let mut total = 0;
-    for i in 0..divisors.len() {
-        let partial_product = product / divisors[i];
-        let inverse = compute_inverse(partial_product, divisors[i]).unwrap();
-        total += remainders[i] * partial_product * inverse;
-    }
+#[derive(Debug, PartialEq)]
+pub enum CrtError {
+    // Moduli have to be positive
+    InvalidModulus { index: usize },
+    // This congruence contradicts the ones before it
+    Incompatible { index: usize },
+}
 
-    total % product
+// Helpers
+fn mod_floor(a: &BigInt, m: &BigInt) -> BigInt {
+    ((a % m) + m) % m
 }
 
-pub fn compute_inverse(a: i64, m: i64) -> Option<i64> {
-    let (g, x, _) = egcd(a, m);
-    if g != 1 {
-        None
+// (g, x, y) such that a * x + b * y = g = gcd(a, b)
+fn egcd_big(a: &BigInt, b: &BigInt) -> (BigInt, BigInt, BigInt) {
+    let zero = BigInt::from(0);
+    if *a == zero {
+        (b.clone(), zero, BigInt::from(1))
     } else {
-        Some((x % m + m) % m)
+        let (g, x, y) = egcd_big(&(b % a), a);
+        let q = b / a;
+        (g, y - q * &x, x)
     }
 }
 
-pub fn egcd(a: i64, b: i64) -> (i64, i64, i64) {
-    if a == 0 {
-        (b, 0, 1)
-    } else {
-        let (g, x, y) = egcd(b % a, a);
-        (g, y - (b / a) * x, x)
+// Folds the congruences into one, two at a time. The moduli don't need to be
+// coprime: merging x = a1 (m1) with x = a2 (m2) works as long as
+// a1 = a2 (mod gcd(m1, m2)), and then gives x mod lcm(m1, m2).
+pub fn crt(congruences: &[Congruence]) -> Result<Congruence, CrtError> {
+    let zero = BigInt::from(0);
+    let mut merged = Congruence::new(0, 1);
+
+    for (index, c) in congruences.iter().enumerate() {
+        if c.modulus <= zero {
+            return Err(CrtError::InvalidModulus { index });
+        }
+
+        let (g, p, _) = egcd_big(&merged.modulus, &c.modulus);
+        let difference = &c.remainder - &merged.remainder;
+        if &difference % &g != zero {
+            return Err(CrtError::Incompatible { index });
+        }
+
+        let lcm = &merged.modulus / &g * &c.modulus;
+        let x = &merged.remainder + difference / &g * p * &merged.modulus;
+        merged = Congruence::new(x, lcm);
     }
+
+    Ok(merged)
 }
 
 #[cfg(test)]
@@ -103,15 +215,113 @@ mod tests {
     fn test_input() {
         assert_eq!(
             input_generator("939\n7,13,x,x,59"),
-            (939 as usize, vec![7, 13, 0, 0, 59])
+            Schedule {
+                earliest: 939,
+                slots: vec![Some(Bus(7)), Some(Bus(13)), None, None, Some(Bus(59))]
+            }
         );
     }
 
+    #[test]
+    #[should_panic(expected = "Invalid bus id: 0")]
+    fn test_zero_bus() {
+        input_generator("939\n7,0,x,13");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid bus id: -7")]
+    fn test_negative_bus() {
+        input_generator("939\nx,-7");
+    }
+
     #[test]
     fn test_part1() {
         assert_eq!(part1(&input_generator("939\n7,13,x,x,59,x,31,19")), 295);
     }
 
+    #[test]
+    fn test_departures() {
+        assert_eq!(Bus(59).next_departure(939), 944);
+        assert_eq!(Bus(7).next_departure(945), 945);
+        assert_eq!(Bus(7).next_departure(0), 0);
+
+        assert_eq!(
+            input_generator("939\n7,x,13").departures(939, 3),
+            vec![
+                (Bus(7), vec![945, 952, 959]),
+                (Bus(13), vec![949, 962, 975])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_queries() {
+        assert_eq!(
+            departures_within(Bus(7), Bus(13), 0, 939),
+            Some((1001, 1001))
+        );
+        assert_eq!(departures_within(Bus(7), Bus(13), 1, 939), Some((987, 988)));
+        assert_eq!(departures_within(Bus(7), Bus(13), 3, 939), Some((952, 949)));
+        assert_eq!(departures_within(Bus(4), Bus(6), 1, 0), Some((0, 0)));
+        assert_eq!(departures_within(Bus(4), Bus(6), 1, 1), Some((12, 12)));
+        assert_eq!(departures_within(Bus(4), Bus(8), 1, 1), Some((8, 8)));
+
+        assert_eq!(
+            first_matching(&[(Bus(17), 0), (Bus(13), 2), (Bus(19), 3)]),
+            Ok(BigInt::from(3417))
+        );
+        assert_eq!(
+            first_matching(&[(Bus(4), 0), (Bus(6), 1)]),
+            Err(CrtError::Incompatible { index: 1 })
+        );
+
+        assert_eq!(
+            input_generator("939\n7,13,x,x,59").timetable(943, 945),
+            "time       bus 7  bus 13  bus 59\n\
+             943            .       .       .\n\
+             944            .       .       D\n\
+             945            D       .       .\n"
+        );
+    }
+
+    #[test]
+    fn test_crt() {
+        let c = |r: i64, m: i64| Congruence::new(r, m);
+        assert_eq!(crt(&[c(2, 3), c(3, 5), c(2, 7)]), Ok(c(23, 105)));
+        assert_eq!(crt(&[]), Ok(c(0, 1)));
+
+        // Not coprime, but consistent
+        assert_eq!(crt(&[c(3, 4), c(5, 6)]), Ok(c(11, 12)));
+        assert_eq!(
+            crt(&[c(1, 4), c(2, 6)]),
+            Err(CrtError::Incompatible { index: 1 })
+        );
+        assert_eq!(
+            crt(&[c(1, 4), c(0, 0)]),
+            Err(CrtError::InvalidModulus { index: 1 })
+        );
+
+        // The product of these primes is way past i64
+        let primes = [
+            1_000_000_007i64,
+            1_000_000_009,
+            998_244_353,
+            1_000_000_021,
+            1_000_000_033,
+        ];
+        let congruences: Vec<Congruence> = primes
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| c(-(i as i64), p))
+            .collect();
+        let solution = crt(&congruences).unwrap();
+        for (i, &p) in primes.iter().enumerate() {
+            let shifted = &solution.remainder + BigInt::from(i);
+            assert_eq!(shifted % BigInt::from(p), BigInt::from(0));
+        }
+        assert!(solution.modulus > BigInt::from(i64::MAX));
+    }
+
     #[test]
     fn test_part2() {
         assert_eq!(part2(&input_generator("939\n7,13,x,x,59,x,31,19")), 1068781);
@@ -1,118 +1,474 @@
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 
-const MEMORY_SIZE: usize = 100000; // Is there an address bigger than this in the input?
-const MAX_36_BITS: u64 = u64::max_value() >> (64 - 36);
+const DEFAULT_WIDTH: u32 = 36;
+
+// All ones in the lowest `width` bits
+fn word_mask(width: u32) -> u64 {
+    u64::MAX >> (64 - width)
+}
+
+// Somewhere to put values. Backends only need to store and sum, sums are u128
+// since a few fully floating writes are enough to go past u64.
+pub trait Memory {
+    fn store(&mut self, address: u64, value: u64);
+    fn sum(&self) -> u128;
+
+    // Word width the backend was set up for, None if it takes any
+    fn width(&self) -> Option<u32> {
+        None
+    }
+
+    // Addresses have to be below this to be stored, None if any of them can
+    fn capacity(&self) -> Option<u64> {
+        None
+    }
+
+    // Writes to every address in the cube, backends that can do better than
+    // one at a time should
+    fn write_floating(&mut self, cube: AddressCube, value: u64) {
+        for address in cube.addresses() {
+            self.store(address, value);
+        }
+    }
+}
+
+// Biggest address DenseMemory agrees to grow to, 16M words being 128 MiB
+pub const DENSE_LIMIT: u64 = 1 << 24;
+
+// Plain Vec that grows up to the biggest address written. Only meant for small
+// address spaces: a full 36 bit one would take 512 GiB, so it only holds
+// addresses below DENSE_LIMIT. BitmaskMachine reports the others as out of
+// range, storing one directly panics.
+#[derive(Debug, Default)]
+pub struct DenseMemory(Vec<u64>);
+
+impl Memory for DenseMemory {
+    fn store(&mut self, address: u64, value: u64) {
+        assert!(
+            address < DENSE_LIMIT,
+            "Address {} is too big for dense memory",
+            address
+        );
+        let address = address as usize;
+        if address >= self.0.len() {
+            self.0.resize(address + 1, 0);
+        }
+        self.0[address] = value;
+    }
+
+    fn sum(&self) -> u128 {
+        self.0.iter().map(|&v| u128::from(v)).sum()
+    }
+
+    fn capacity(&self) -> Option<u64> {
+        Some(DENSE_LIMIT)
+    }
+}
+
+impl Memory for HashMap<u64, u64> {
+    fn store(&mut self, address: u64, value: u64) {
+        self.insert(address, value);
+    }
+
+    fn sum(&self) -> u128 {
+        self.values().map(|&v| u128::from(v)).sum()
+    }
+}
+
+impl Memory for BTreeMap<u64, u64> {
+    fn store(&mut self, address: u64, value: u64) {
+        self.insert(address, value);
+    }
+
+    fn sum(&self) -> u128 {
+        self.values().map(|&v| u128::from(v)).sum()
+    }
+}
+
+// Every address of a `width` bit word matching a pattern: bits in `fixed` have
+// the value they have in `bits`, the rest float and can be anything
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AddressCube {
+    width: u32,
+    fixed: u64,
+    bits: u64,
+}
+
+impl AddressCube {
+    pub fn new(width: u32, fixed: u64, bits: u64) -> Self {
+        let fixed = fixed & word_mask(width);
+        AddressCube {
+            width,
+            fixed,
+            bits: bits & fixed,
+        }
+    }
+
+    pub fn size(&self) -> u128 {
+        1 << (self.width - self.fixed.count_ones())
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address & !word_mask(self.width) == 0 && address & self.fixed == self.bits
+    }
+
+    // Biggest address in the cube, all floating bits set
+    pub fn highest(&self) -> u64 {
+        self.bits | (!self.fixed & word_mask(self.width))
+    }
+
+    pub fn intersects(&self, other: &AddressCube) -> bool {
+        assert_eq!(self.width, other.width, "Cubes have different widths");
+        (self.bits ^ other.bits) & self.fixed & other.fixed == 0
+    }
+
+    // Goes through every subset of the floating bits, in increasing order
+    pub fn addresses(&self) -> impl Iterator<Item = u64> {
+        let floating = !self.fixed & word_mask(self.width);
+        let bits = self.bits;
+        let mut next = Some(0u64);
+
+        std::iter::from_fn(move || {
+            let current = next?;
+            next = if current == floating {
+                None
+            } else {
+                Some(current.wrapping_sub(floating) & floating)
+            };
+            Some(bits | current)
+        })
+    }
+
+    // Splits self minus other into disjoint cubes. Each bit other fixes but
+    // self doesn't cuts off the half that disagrees with other, what's left
+    // at the end is inside other and gets dropped.
+    pub fn subtract(&self, other: &AddressCube) -> Vec<AddressCube> {
+        if !self.intersects(other) {
+            return vec![*self];
+        }
+
+        let mut pieces = Vec::new();
+        let mut rest = *self;
+        let mut cuts = other.fixed & !self.fixed;
+        while cuts != 0 {
+            let bit = cuts & cuts.wrapping_neg();
+            cuts &= !bit;
+
+            pieces.push(AddressCube::new(
+                self.width,
+                rest.fixed | bit,
+                rest.bits | (!other.bits & bit),
+            ));
+            rest = AddressCube::new(self.width, rest.fixed | bit, rest.bits | (other.bits & bit));
+        }
+
+        pieces
+    }
+}
+
+// Memory for the version 2 decoder that never expands floating bits. Cubes are
+// kept disjoint, so each new write is cut out of everything before it.
+#[derive(Debug)]
+pub struct SymbolicMemory {
+    width: u32,
+    cubes: Vec<(AddressCube, u64)>,
+}
+
+impl SymbolicMemory {
+    pub fn new(width: u32) -> Self {
+        SymbolicMemory {
+            width,
+            cubes: Vec::new(),
+        }
+    }
+
+    pub fn read(&self, address: u64) -> u64 {
+        self.cubes
+            .iter()
+            .find(|(c, _)| c.contains(address))
+            .map_or(0, |&(_, v)| v)
+    }
+}
+
+impl Default for SymbolicMemory {
+    fn default() -> Self {
+        SymbolicMemory::new(DEFAULT_WIDTH)
+    }
+}
+
+impl Memory for SymbolicMemory {
+    fn store(&mut self, address: u64, value: u64) {
+        assert!(
+            address & !word_mask(self.width) == 0,
+            "Address {} doesn't fit in {} bits",
+            address,
+            self.width
+        );
+        self.write_floating(AddressCube::new(self.width, u64::MAX, address), value);
+    }
+
+    fn write_floating(&mut self, cube: AddressCube, value: u64) {
+        assert_eq!(
+            cube.width, self.width,
+            "Cube doesn't match the memory width"
+        );
+        let mut cubes = Vec::with_capacity(self.cubes.len() + 1);
+        for (c, v) in self.cubes.drain(..) {
+            cubes.extend(c.subtract(&cube).into_iter().map(|piece| (piece, v)));
+        }
+
+        // Zeroes only matter for what they overwrite
+        if value != 0 {
+            cubes.push((cube, value));
+        }
+        self.cubes = cubes;
+    }
+
+    fn sum(&self) -> u128 {
+        self.cubes
+            .iter()
+            .map(|&(c, v)| c.size() * u128::from(v))
+            .sum()
+    }
+
+    fn width(&self) -> Option<u32> {
+        Some(self.width)
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Instruction {
     value: u64,
-    address: usize,
+    address: u64,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Program {
+    // Width the mask was parsed for
+    width: u32,
     ones_mask: u64,
     zeroes_mask: u64,
     xs_mask: u64,
     instructions: Vec<Instruction>,
 }
 
-#[aoc_generator(day14)]
-pub fn input_generator(input: &str) -> Vec<Program> {
-    let instruction_re = Regex::new(r"mem\[(.*)\] = (.*)").unwrap();
-    let mut program_vec = Vec::new();
-
-    for block in input.split("mask = ") {
-        let mut ones_mask = 0;
-        let mut zeroes_mask = 0;
-        let mut instructions = Vec::new();
-
-        for l in block.lines() {
-            if !instruction_re.is_match(l) {
-                for (i, c) in l.chars().enumerate() {
-                    match c {
-                        '0' => zeroes_mask |= 1 << (35 - i),
-                        '1' => ones_mask |= 1 << (35 - i),
-                        'X' => (),
-                        _ => panic!("Invalid mask input"),
-                    }
-                }
-            } else {
-                let cap = instruction_re.captures(l).unwrap();
-                instructions.push(Instruction {
-                    value: cap[2].parse::<u64>().unwrap(),
-                    address: cap[1].parse::<usize>().unwrap(),
+// Lines are counted from 1
+#[derive(Debug, PartialEq)]
+pub enum MachineError {
+    InvalidWidth(u32),
+    WidthMismatch { expected: u32, found: u32 },
+    InvalidLine { line: usize },
+    InvalidMask { line: usize },
+    WrongMaskLength { line: usize, length: usize },
+    MissingMask { line: usize },
+    AddressOutOfRange { address: u64 },
+    ValueOutOfRange { value: u64 },
+}
+
+pub fn parse_programs(input: &str, width: u32) -> Result<Vec<Program>, MachineError> {
+    if width == 0 || width > 64 {
+        return Err(MachineError::InvalidWidth(width));
+    }
+
+    let instruction_re = Regex::new(r"^mem\[(\d+)\] = (\d+)$").unwrap();
+    let mut program_vec: Vec<Program> = Vec::new();
+
+    for (n, l) in input.lines().enumerate() {
+        let line = n + 1;
+        if l.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(mask) = l.strip_prefix("mask = ") {
+            if mask.chars().count() != width as usize {
+                return Err(MachineError::WrongMaskLength {
+                    line,
+                    length: mask.chars().count(),
                 });
             }
-        }
 
-        if !instructions.is_empty() {
+            let mut ones_mask = 0;
+            let mut zeroes_mask = 0;
+            for (i, c) in mask.chars().enumerate() {
+                let bit = 1 << (width as usize - 1 - i);
+                match c {
+                    '0' => zeroes_mask |= bit,
+                    '1' => ones_mask |= bit,
+                    'X' => (),
+                    _ => return Err(MachineError::InvalidMask { line }),
+                }
+            }
+
             program_vec.push(Program {
+                width,
                 ones_mask,
                 zeroes_mask,
                 xs_mask: ones_mask | zeroes_mask, // This is an inverted bit mask
-                instructions,
+                instructions: Vec::new(),
             });
+        } else if let Some(cap) = instruction_re.captures(l) {
+            let instruction = Instruction {
+                value: cap[2]
+                    .parse::<u64>()
+                    .map_err(|_| MachineError::InvalidLine { line })?,
+                address: cap[1]
+                    .parse::<u64>()
+                    .map_err(|_| MachineError::InvalidLine { line })?,
+            };
+            program_vec
+                .last_mut()
+                .ok_or(MachineError::MissingMask { line })?
+                .instructions
+                .push(instruction);
+        } else {
+            return Err(MachineError::InvalidLine { line });
         }
     }
 
-    program_vec
+    program_vec.retain(|p| !p.instructions.is_empty());
+    Ok(program_vec)
 }
 
-#[aoc(day14, part1)]
-pub fn part1(programs: &Vec<Program>) -> u64 {
-    let mut memory = [0 as u64; MEMORY_SIZE];
+#[aoc_generator(day14)]
+pub fn input_generator(input: &str) -> Vec<Program> {
+    parse_programs(input, DEFAULT_WIDTH).unwrap_or_else(|e| panic!("Invalid input: {:?}", e))
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Decoder {
+    // Version 1, the mask changes the value
+    Values,
+    // Version 2, the mask changes the address and X bits float
+    Addresses,
+}
+
+// Runs programs one instruction at a time, so memory can be looked at in
+// between
+pub struct BitmaskMachine<'a, M> {
+    width: u32,
+    decoder: Decoder,
+    programs: &'a [Program],
+    memory: M,
+    program: usize,
+    instruction: usize,
+}
 
-    for p in programs {
-        for i in p.instructions.iter() {
-            memory[i.address] = (i.value | p.ones_mask) & !p.zeroes_mask;
+impl<'a, M: Memory> BitmaskMachine<'a, M> {
+    pub fn new(
+        programs: &'a [Program],
+        width: u32,
+        decoder: Decoder,
+        memory: M,
+    ) -> Result<Self, MachineError> {
+        if width == 0 || width > 64 {
+            return Err(MachineError::InvalidWidth(width));
         }
+
+        // Programs and memory have to agree with the machine on the width
+        let found = programs
+            .iter()
+            .map(|p| p.width)
+            .chain(memory.width())
+            .find(|&w| w != width);
+        if let Some(found) = found {
+            return Err(MachineError::WidthMismatch {
+                expected: width,
+                found,
+            });
+        }
+
+        Ok(BitmaskMachine {
+            width,
+            decoder,
+            programs,
+            memory,
+            program: 0,
+            instruction: 0,
+        })
     }
 
-    check_sums(memory)
-}
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
 
-#[aoc(day14, part2)]
-pub fn part2(programs: &Vec<Program>) -> u64 {
-    // For part2 the full 32 bits are needed
-    let mut memory = HashMap::new();
-
-    for p in programs {
-        for inst in p.instructions.iter() {
-            let base_addr = (inst.address as u64 | p.ones_mask) & p.xs_mask;
-
-            let mut curr_mask = p.xs_mask;
-            loop {
-                memory.insert(base_addr | (!curr_mask & MAX_36_BITS), inst.value);
-                if curr_mask & MAX_36_BITS == MAX_36_BITS {
-                    break;
-                }
-                curr_mask = (curr_mask + 1) | p.xs_mask;
+    pub fn into_memory(self) -> M {
+        self.memory
+    }
+
+    // Runs the next instruction, returns false once there's nothing left
+    pub fn step(&mut self) -> Result<bool, MachineError> {
+        let p = match self.programs.get(self.program) {
+            Some(p) => p,
+            None => return Ok(false),
+        };
+        let inst = &p.instructions[self.instruction];
+        let word = word_mask(self.width);
+
+        if inst.address & !word != 0 {
+            return Err(MachineError::AddressOutOfRange {
+                address: inst.address,
+            });
+        }
+        if inst.value & !word != 0 {
+            return Err(MachineError::ValueOutOfRange { value: inst.value });
+        }
+
+        // The memory might not hold every address of the word
+        let highest = match self.decoder {
+            Decoder::Values => inst.address,
+            Decoder::Addresses => {
+                AddressCube::new(self.width, p.xs_mask, inst.address | p.ones_mask).highest()
             }
+        };
+        if self.memory.capacity().is_some_and(|c| highest >= c) {
+            return Err(MachineError::AddressOutOfRange { address: highest });
+        }
+
+        match self.decoder {
+            Decoder::Values => self
+                .memory
+                .store(inst.address, (inst.value | p.ones_mask) & !p.zeroes_mask),
+            Decoder::Addresses => {
+                let cube = AddressCube::new(self.width, p.xs_mask, inst.address | p.ones_mask);
+                self.memory.write_floating(cube, inst.value);
+            }
+        }
+
+        self.instruction += 1;
+        if self.instruction == p.instructions.len() {
+            self.program += 1;
+            self.instruction = 0;
         }
+
+        Ok(true)
     }
 
-    check_sums_hash(memory)
-}
+    pub fn run(&mut self) -> Result<(), MachineError> {
+        while self.step()? {}
 
-// Helpers
-fn check_sums(memory: [u64; MEMORY_SIZE]) -> u64 {
-    let mut sum = 0;
-    for i in memory.iter() {
-        sum += i;
+        Ok(())
     }
+}
 
-    sum
+fn run_to_sum<M: Memory>(programs: &[Program], decoder: Decoder, memory: M) -> u64 {
+    let mut machine = BitmaskMachine::new(programs, DEFAULT_WIDTH, decoder, memory).unwrap();
+    machine
+        .run()
+        .unwrap_or_else(|e| panic!("Program failed: {:?}", e));
+
+    u64::try_from(machine.memory().sum()).expect("Sum doesn't fit in a u64!")
 }
 
-fn check_sums_hash(memory: HashMap<u64, u64>) -> u64 {
-    let mut sum = 0;
-    for (_, value) in memory.iter() {
-        sum += value;
-    }
+#[aoc(day14, part1)]
+pub fn part1(programs: &Vec<Program>) -> u64 {
+    run_to_sum(programs, Decoder::Values, HashMap::new())
+}
 
-    sum
+#[aoc(day14, part2)]
+pub fn part2(programs: &Vec<Program>) -> u64 {
+    run_to_sum(programs, Decoder::Addresses, SymbolicMemory::default())
 }
 
 #[cfg(test)]
@@ -132,6 +488,7 @@ mod tests {
             ),
             vec![
                 Program {
+                    width: 36,
                     ones_mask: 64,
                     zeroes_mask: 2,
                     xs_mask: 66,
@@ -151,6 +508,7 @@ mod tests {
                     ]
                 },
                 Program {
+                    width: 36,
                     ones_mask: 64,
                     zeroes_mask: 2,
                     xs_mask: 66,
@@ -188,4 +546,283 @@ mod tests {
             208
         );
     }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_programs("mask = X1X0\nmem[3] = 4", 4),
+            Ok(vec![Program {
+                width: 4,
+                ones_mask: 4,
+                zeroes_mask: 1,
+                xs_mask: 5,
+                instructions: vec![Instruction {
+                    value: 4,
+                    address: 3
+                }]
+            }])
+        );
+        assert_eq!(
+            parse_programs("mask = X1X0\nmem[3] = 4", 36),
+            Err(MachineError::WrongMaskLength { line: 1, length: 4 })
+        );
+        assert_eq!(
+            parse_programs("mask = X1Y0", 4),
+            Err(MachineError::InvalidMask { line: 1 })
+        );
+        assert_eq!(
+            parse_programs("mem[3] = 4", 4),
+            Err(MachineError::MissingMask { line: 1 })
+        );
+        assert_eq!(
+            parse_programs("mask = XXXX\nmem[3] == 4", 4),
+            Err(MachineError::InvalidLine { line: 2 })
+        );
+        assert_eq!(parse_programs("", 65), Err(MachineError::InvalidWidth(65)));
+    }
+
+    #[test]
+    fn test_memory_backends() {
+        let programs = input_generator(
+            "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXX1XXXX0X\n\
+             mem[8] = 11\n\
+             mem[7] = 101\n\
+             mem[8] = 0\n\
+             mem[1000000] = 1",
+        );
+        let sum = |memory: &dyn Memory| memory.sum();
+
+        let mut dense =
+            BitmaskMachine::new(&programs, 36, Decoder::Values, DenseMemory::default()).unwrap();
+        let mut hash = BitmaskMachine::new(&programs, 36, Decoder::Values, HashMap::new()).unwrap();
+        let mut btree =
+            BitmaskMachine::new(&programs, 36, Decoder::Values, BTreeMap::new()).unwrap();
+        dense.run().unwrap();
+        hash.run().unwrap();
+        btree.run().unwrap();
+        assert_eq!(sum(dense.memory()), 230);
+        assert_eq!(sum(hash.memory()), 230);
+        assert_eq!(sum(btree.memory()), 230);
+
+        let programs = input_generator(
+            "mask = 000000000000000000000000000000X1001X\n\
+             mem[42] = 100\n\
+             mask = 00000000000000000000000000000000X0XX\n\
+             mem[26] = 1",
+        );
+        let mut btree =
+            BitmaskMachine::new(&programs, 36, Decoder::Addresses, BTreeMap::new()).unwrap();
+        btree.run().unwrap();
+        assert_eq!(btree.memory().sum(), 208);
+        assert_eq!(btree.memory().keys().next(), Some(&16));
+    }
+
+    #[test]
+    fn test_sparse_part1() {
+        // The highest 36 bit address is fine, it just can't go in a Vec
+        let programs = input_generator(
+            "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX\n\
+             mem[68719476735] = 1\n\
+             mem[34359738367] = 2",
+        );
+        assert_eq!(part1(&programs), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "too big for dense memory")]
+    fn test_dense_limit() {
+        DenseMemory::default().store(34359738367, 1);
+    }
+
+    #[test]
+    fn test_dense_capacity() {
+        let programs = input_generator(
+            "mask = 000000000000000000000000000000000000\n\
+             mem[34359738367] = 1",
+        );
+        let mut machine =
+            BitmaskMachine::new(&programs, 36, Decoder::Values, DenseMemory::default()).unwrap();
+        assert_eq!(
+            machine.run(),
+            Err(MachineError::AddressOutOfRange {
+                address: 34359738367
+            })
+        );
+
+        // One floating bit is enough to go past the limit
+        let programs = input_generator(
+            "mask = 00000000000X000000000000000000000000\n\
+             mem[5] = 1",
+        );
+        let mut machine =
+            BitmaskMachine::new(&programs, 36, Decoder::Addresses, DenseMemory::default()).unwrap();
+        assert_eq!(
+            machine.run(),
+            Err(MachineError::AddressOutOfRange {
+                address: (1 << 24) | 5
+            })
+        );
+
+        let programs = input_generator("mask = 0000000000000000000000000000000000X1\nmem[8] = 3");
+        let mut machine =
+            BitmaskMachine::new(&programs, 36, Decoder::Addresses, DenseMemory::default()).unwrap();
+        machine.run().unwrap();
+        assert_eq!(machine.memory().sum(), 6);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let programs = input_generator(
+            "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX\n\
+             mem[68719476736] = 1",
+        );
+        for &decoder in [Decoder::Values, Decoder::Addresses].iter() {
+            let mut machine = BitmaskMachine::new(&programs, 36, decoder, HashMap::new()).unwrap();
+            assert_eq!(
+                machine.run(),
+                Err(MachineError::AddressOutOfRange { address: 1 << 36 })
+            );
+        }
+
+        let programs = parse_programs("mask = XXXX\nmem[1] = 16", 4).unwrap();
+        let mut machine =
+            BitmaskMachine::new(&programs, 4, Decoder::Values, HashMap::new()).unwrap();
+        assert_eq!(
+            machine.run(),
+            Err(MachineError::ValueOutOfRange { value: 16 })
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        let programs = parse_programs(
+            "mask = 1XX0\n\
+             mem[1] = 3\n\
+             mem[2] = 7\n\
+             mask = XXXX\n\
+             mem[1] = 0",
+            4,
+        )
+        .unwrap();
+        let mut machine =
+            BitmaskMachine::new(&programs, 4, Decoder::Values, BTreeMap::new()).unwrap();
+
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.memory().get(&1), Some(&10));
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.memory().get(&2), Some(&14));
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.memory().get(&1), Some(&0));
+        assert_eq!(machine.step(), Ok(false));
+        assert_eq!(machine.into_memory().len(), 2);
+
+        // Same thing with the address decoder, a 64 bit word and nothing floating
+        let programs =
+            parse_programs(&format!("mask = {}\nmem[5] = 9", "0".repeat(64)), 64).unwrap();
+        let mut machine =
+            BitmaskMachine::new(&programs, 64, Decoder::Addresses, HashMap::new()).unwrap();
+        machine.run().unwrap();
+        assert_eq!(machine.memory().get(&5), Some(&9));
+    }
+
+    #[test]
+    fn test_address_cube() {
+        let a = AddressCube::new(4, 0b1100, 0b1000);
+        let b = AddressCube::new(4, 0b0110, 0b0010);
+        assert_eq!(a.size(), 4);
+        assert_eq!(AddressCube::new(36, 0b1100, 0b1000).size(), 1 << 34);
+        assert!(a.contains(0b1011) && !a.contains(0b0011) && !a.contains(0b11000));
+        assert!(a.intersects(&b));
+        assert!(!a.intersects(&AddressCube::new(4, 0b1000, 0)));
+        assert_eq!(a.addresses().collect::<Vec<u64>>(), vec![8, 9, 10, 11]);
+
+        // a has bit 1 floating and b fixes it to 1, so only the bit 1 = 0 half is left
+        let rest = a.subtract(&b);
+        assert_eq!(rest, vec![AddressCube::new(4, 0b1110, 0b1000)]);
+        assert_eq!(a.subtract(&a), vec![]);
+        assert_eq!(b.subtract(&AddressCube::new(4, 0, 0)), vec![]);
+    }
+
+    #[test]
+    fn test_symbolic_memory() {
+        let programs = input_generator(
+            "mask = 000000000000000000000000000000X1001X\n\
+             mem[42] = 100\n\
+             mask = 00000000000000000000000000000000X0XX\n\
+             mem[26] = 1",
+        );
+        let mut symbolic =
+            BitmaskMachine::new(&programs, 36, Decoder::Addresses, SymbolicMemory::default())
+                .unwrap();
+        let mut enumerated =
+            BitmaskMachine::new(&programs, 36, Decoder::Addresses, HashMap::new()).unwrap();
+        symbolic.run().unwrap();
+        enumerated.run().unwrap();
+        for address in 0..64 {
+            assert_eq!(
+                symbolic.memory().read(address),
+                *enumerated.memory().get(&address).unwrap_or(&0)
+            );
+        }
+        assert_eq!(symbolic.memory().sum(), 208);
+
+        // Way too many addresses to write one by one
+        let programs = input_generator(
+            "mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX\n\
+             mem[0] = 5\n\
+             mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX1\n\
+             mem[0] = 3\n\
+             mask = XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX0X\n\
+             mem[0] = 0",
+        );
+        let mut machine =
+            BitmaskMachine::new(&programs, 36, Decoder::Addresses, SymbolicMemory::default())
+                .unwrap();
+        machine.run().unwrap();
+        let symbolic = machine.into_memory();
+        assert_eq!(symbolic.sum(), (1 << 34) * 5 + (1 << 34) * 3);
+        assert_eq!(symbolic.read(0b10), 5);
+        assert_eq!(symbolic.read(0b11), 3);
+        assert_eq!(symbolic.read(0b01), 0);
+    }
+
+    #[test]
+    fn test_width_mismatch() {
+        let programs = parse_programs("mask = X1X0\nmem[3] = 4", 4).unwrap();
+        assert_eq!(
+            BitmaskMachine::new(&programs, 36, Decoder::Values, HashMap::new()).err(),
+            Some(MachineError::WidthMismatch {
+                expected: 36,
+                found: 4
+            })
+        );
+        assert!(BitmaskMachine::new(&programs, 4, Decoder::Values, HashMap::new()).is_ok());
+
+        let programs =
+            parse_programs(&format!("mask = {}\nmem[5] = 9", "0".repeat(64)), 64).unwrap();
+        assert_eq!(
+            BitmaskMachine::new(&programs, 64, Decoder::Addresses, SymbolicMemory::default()).err(),
+            Some(MachineError::WidthMismatch {
+                expected: 64,
+                found: 36
+            })
+        );
+        let mut machine =
+            BitmaskMachine::new(&programs, 64, Decoder::Addresses, SymbolicMemory::new(64))
+                .unwrap();
+        machine.run().unwrap();
+        assert_eq!(machine.memory().read(5), 9);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit in 36 bits")]
+    fn test_symbolic_store_out_of_range() {
+        SymbolicMemory::default().store(1 << 40, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "different widths")]
+    fn test_cube_widths() {
+        AddressCube::new(4, 0b1100, 0).subtract(&AddressCube::new(36, 0b1100, 0));
+    }
 }
@@ -1,52 +1,224 @@
-#[aoc_generator(day15)]
-pub fn input_generator(input: &str) -> Vec<usize> {
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum SeedError {
+    // Nothing to start the game with
+    Empty,
+    // The seed at `position` (counting from 0) isn't a number
+    Invalid { position: usize, seed: String },
+}
+
+impl fmt::Display for SeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SeedError::Empty => write!(f, "No starting numbers given"),
+            SeedError::Invalid { position, seed } => {
+                write!(f, "Starting number {} isn't a number: {:?}", position, seed)
+            }
+        }
+    }
+}
+
+pub fn parse_seeds(input: &str) -> Result<Vec<usize>, SeedError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(SeedError::Empty);
+    }
+
     input
-        .split(",")
-        .map(|l| l.parse::<usize>().unwrap())
+        .split(',')
+        .enumerate()
+        .map(|(position, seed)| {
+            seed.trim()
+                .parse::<usize>()
+                .map_err(|_| SeedError::Invalid {
+                    position,
+                    seed: seed.to_string(),
+                })
+        })
         .collect()
 }
 
-#[aoc(day15, part1)]
-pub fn part1(starting: &Vec<usize>) -> usize {
-    const ITERATIONS: usize = 2020;
-    let numbers = starting.clone();
-    let last_index = vec![None; ITERATIONS];
+#[aoc_generator(day15)]
+pub fn input_generator(input: &str) -> Vec<usize> {
+    parse_seeds(input).unwrap_or_else(|e| panic!("{}", e))
+}
 
-    play_game(numbers, last_index, ITERATIONS)
+// Turn each value was last spoken on, 0 being never since turns start at 1.
+// Values below `limit` go in a flat table, anything bigger (a huge seed, or
+// an age from very far into the game) in a map, so seeds can't make the table
+// blow up.
+struct LastSeen {
+    table: Vec<u32>,
+    big: HashMap<usize, u32>,
+    limit: usize,
 }
 
-#[aoc(day15, part2)]
-pub fn part2(starting: &Vec<usize>) -> usize {
-    const ITERATIONS: usize = 30000000;
-    let numbers = starting.clone();
-    let last_index = vec![None; ITERATIONS];
+impl LastSeen {
+    // The whole table up front
+    fn dense(limit: usize) -> Self {
+        LastSeen {
+            table: vec![0; limit],
+            big: HashMap::new(),
+            limit,
+        }
+    }
+
+    // The table grows as bigger values show up, up to `limit`
+    fn growing(limit: usize) -> Self {
+        LastSeen {
+            table: Vec::new(),
+            big: HashMap::new(),
+            limit,
+        }
+    }
+
+    // Records the value as spoken on `turn`, returns when it was before that
+    fn replace(&mut self, value: usize, turn: u32) -> u32 {
+        if value >= self.limit {
+            return self.big.insert(value, turn).unwrap_or(0);
+        }
 
-    play_game(numbers, last_index, ITERATIONS)
+        if value >= self.table.len() {
+            let size = (value + 1).max(2 * self.table.len()).min(self.limit);
+            self.table.resize(size, 0);
+        }
+        std::mem::replace(&mut self.table[value], turn)
+    }
 }
 
-// Helpers
-fn play_game(
-    mut numbers: Vec<usize>,
-    mut last_index: Vec<Option<usize>>,
-    iterations: usize,
-) -> usize {
-    for (i, n) in numbers.iter().enumerate() {
-        last_index[n.to_owned()] = Some(i + 1);
-    }
-
-    for j in numbers.len()..iterations {
-        match last_index[numbers[j - 1]] {
-            Some(last_num) => {
-                numbers.push(j - last_num);
+// Biggest table MemoryGame keeps, 256 MiB worth of u32
+const TABLE_LIMIT: usize = 1 << 26;
+
+// The game as an endless list of (turn, value, age), turns starting at 1 and
+// age being how many turns ago the value was last spoken (0 if never, which is
+// also what gets spoken next). Unlike play_game the table grows as needed, so
+// nothing has to be known up front. Turns are stored as u32, so the game
+// stops after turn u32::MAX rather than getting the ages wrong.
+pub struct MemoryGame {
+    seeds: Vec<usize>,
+    last_seen: LastSeen,
+    turn: usize,
+    age: usize,
+}
+
+impl MemoryGame {
+    pub fn new(seeds: &[usize]) -> Self {
+        MemoryGame {
+            seeds: seeds.to_vec(),
+            last_seen: LastSeen::growing(TABLE_LIMIT),
+            turn: 0,
+            age: 0,
+        }
+    }
+
+    // Value spoken on the given turn, None if the game is already past it
+    pub fn value_at(&mut self, turn: usize) -> Option<usize> {
+        if turn <= self.turn {
+            return None;
+        }
+
+        self.nth(turn - self.turn - 1).map(|(_, value, _)| value)
+    }
+
+    // Plays `turns` turns, sampling how many different values have been
+    // spoken every `sample_every` turns (and on the last one)
+    pub fn statistics(seeds: &[usize], turns: usize, sample_every: usize) -> GameStatistics {
+        let mut stats = GameStatistics {
+            first_occurrence: HashMap::new(),
+            largest_gap: None,
+            distinct_growth: Vec::new(),
+        };
+
+        for (turn, value, age) in MemoryGame::new(seeds).take(turns) {
+            stats.first_occurrence.entry(value).or_insert(turn);
+            // None < Some(anything), so the first turn always counts
+            if stats.largest_gap.map(|(_, _, gap)| gap) < Some(age) {
+                stats.largest_gap = Some((turn, value, age));
             }
-            None => {
-                numbers.push(0);
+            if turn % sample_every.max(1) == 0 || turn == turns {
+                stats
+                    .distinct_growth
+                    .push((turn, stats.first_occurrence.len()));
             }
         }
-        last_index[numbers[j - 1]] = Some(j);
+
+        stats
     }
+}
 
-    numbers.last().unwrap().to_owned()
+impl Iterator for MemoryGame {
+    type Item = (usize, usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.turn >= u32::MAX as usize {
+            return None;
+        }
+        self.turn += 1;
+        let value = match self.seeds.get(self.turn - 1) {
+            Some(&seed) => seed,
+            None => self.age,
+        };
+
+        let seen = self.last_seen.replace(value, self.turn as u32) as usize;
+        self.age = if seen == 0 { 0 } else { self.turn - seen };
+
+        Some((self.turn, value, self.age))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GameStatistics {
+    // Turn on which each value was first spoken
+    pub first_occurrence: HashMap<usize, usize>,
+    // (turn, value, age) with the biggest age
+    pub largest_gap: Option<(usize, usize, usize)>,
+    // (turn, distinct values spoken so far)
+    pub distinct_growth: Vec<(usize, usize)>,
+}
+
+#[aoc(day15, part1)]
+pub fn part1(starting: &Vec<usize>) -> usize {
+    play_game(starting, 2020).unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[aoc(day15, part2)]
+pub fn part2(starting: &Vec<usize>) -> usize {
+    play_game(starting, 30000000).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// Helpers
+
+// Number spoken on turn `iterations`, turns starting at 1
+fn play_game(starting: &[usize], iterations: usize) -> Result<usize, SeedError> {
+    assert!(iterations > 0, "Turns start at 1");
+    assert!(
+        iterations <= u32::MAX as usize,
+        "Too many turns for u32 turn numbers"
+    );
+    if starting.is_empty() {
+        return Err(SeedError::Empty);
+    }
+    if iterations <= starting.len() {
+        return Ok(starting[iterations - 1]);
+    }
+
+    // The number just spoken is kept aside, so it isn't in here yet. Numbers
+    // spoken after the seeds are ages, so always below `iterations`, only
+    // seeds can end up in the map.
+    let mut last_seen = LastSeen::dense(iterations);
+    for (i, &n) in starting[..starting.len() - 1].iter().enumerate() {
+        last_seen.replace(n, (i + 1) as u32);
+    }
+
+    let mut current = starting[starting.len() - 1];
+    for turn in starting.len()..iterations {
+        let seen = last_seen.replace(current, turn as u32) as usize;
+        current = if seen == 0 { 0 } else { turn - seen };
+    }
+
+    Ok(current)
 }
 
 #[cfg(test)]
@@ -58,9 +230,114 @@ mod tests {
         assert_eq!(part1(&input_generator("0,3,6")), 436);
     }
 
+    #[test]
+    fn test_play_game() {
+        let expected = [0, 3, 6, 0, 3, 3, 1, 0, 4, 0];
+        for (turn, &n) in expected.iter().enumerate() {
+            assert_eq!(play_game(&[0, 3, 6], turn + 1), Ok(n));
+        }
+        assert_eq!(play_game(&[1, 3, 2], 2020), Ok(1));
+        assert_eq!(play_game(&[3, 1, 2], 2020), Ok(1836));
+
+        // Seeds bigger than the number of turns
+        assert_eq!(
+            play_game(&[5000, 1], 2020),
+            Ok(MemoryGame::new(&[5000, 1]).value_at(2020).unwrap())
+        );
+        assert_eq!(play_game(&[5000, 1], 4), Ok(0));
+
+        // Way bigger, these mustn't end up sizing any table
+        for &big in [5_000_000_000, usize::MAX].iter() {
+            let seeds = [big, 3, big];
+            let expected = MemoryGame::new(&seeds).value_at(2020).unwrap();
+            assert_eq!(play_game(&seeds, 2020), Ok(expected));
+            assert_eq!(play_game(&seeds, 3), Ok(big));
+            assert_eq!(play_game(&seeds, 4), Ok(2));
+            assert_eq!(play_game(&seeds, 5), Ok(0));
+        }
+        assert_eq!(play_game(&[], 2020), Err(SeedError::Empty));
+    }
+
+    #[test]
+    fn test_seeds() {
+        assert_eq!(parse_seeds("0,3,6\n"), Ok(vec![0, 3, 6]));
+        assert_eq!(parse_seeds(" 5000, 1"), Ok(vec![5000, 1]));
+        assert_eq!(parse_seeds(""), Err(SeedError::Empty));
+        assert_eq!(parse_seeds("\n"), Err(SeedError::Empty));
+        assert_eq!(
+            parse_seeds("0,x,6"),
+            Err(SeedError::Invalid {
+                position: 1,
+                seed: "x".to_string()
+            })
+        );
+        assert_eq!(
+            parse_seeds("0,,6"),
+            Err(SeedError::Invalid {
+                position: 1,
+                seed: "".to_string()
+            })
+        );
+        assert_eq!(
+            parse_seeds("1,-2").unwrap_err().to_string(),
+            "Starting number 1 isn't a number: \"-2\""
+        );
+    }
+
+    #[test]
+    fn test_memory_game() {
+        let turns: Vec<(usize, usize, usize)> = MemoryGame::new(&[0, 3, 6]).take(10).collect();
+        assert_eq!(
+            turns,
+            vec![
+                (1, 0, 0),
+                (2, 3, 0),
+                (3, 6, 0),
+                (4, 0, 3),
+                (5, 3, 3),
+                (6, 3, 1),
+                (7, 1, 0),
+                (8, 0, 4),
+                (9, 4, 0),
+                (10, 0, 2)
+            ]
+        );
+
+        let mut game = MemoryGame::new(&[0, 3, 6]);
+        assert_eq!(game.value_at(10), Some(0));
+        assert_eq!(game.value_at(10), None);
+        assert_eq!(game.value_at(2020), Some(436));
+
+        // Repeated and large seeds are fine too
+        let values: Vec<usize> = MemoryGame::new(&[5000, 5000, 1])
+            .take(5)
+            .map(|t| t.1)
+            .collect();
+        assert_eq!(values, vec![5000, 5000, 1, 0, 0]);
+
+        // Nothing past the last turn that fits a u32
+        let mut game = MemoryGame::new(&[0, 3, 6]);
+        game.turn = u32::MAX as usize - 1;
+        assert_eq!(game.next().map(|t| t.0), Some(u32::MAX as usize));
+        assert_eq!(game.next(), None);
+        assert_eq!(game.value_at(u32::MAX as usize + 1), None);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let stats = MemoryGame::statistics(&[0, 3, 6], 10, 4);
+        assert_eq!(
+            stats.first_occurrence,
+            vec![(0, 1), (3, 2), (6, 3), (1, 7), (4, 9)]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(stats.largest_gap, Some((8, 0, 4)));
+        assert_eq!(stats.distinct_growth, vec![(4, 3), (8, 4), (10, 5)]);
+    }
+
     #[test]
     fn test_part2() {
-        // This test can be slow, leaving it commented for the future
-        //assert_eq!(part2(&input_generator("0,3,6")), 175594);
+        assert_eq!(part2(&input_generator("0,3,6")), 175594);
     }
 }
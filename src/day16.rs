@@ -1,12 +1,94 @@
-use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+// Inclusive ranges, kept sorted and merged so that none of them overlap or
+// touch, which lets membership be a binary search
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct IntervalSet {
+    ranges: Vec<(usize, usize)>,
+}
+
+impl IntervalSet {
+    // Ranges can come in any order and overlap, backwards ones are empty
+    pub fn new(ranges: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        let mut sorted: Vec<(usize, usize)> = ranges.into_iter().filter(|r| r.0 <= r.1).collect();
+        sorted.sort_unstable();
+
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(sorted.len());
+        for (first, last) in sorted {
+            match merged.last_mut() {
+                Some(prev) if first <= prev.1.saturating_add(1) => prev.1 = prev.1.max(last),
+                _ => merged.push((first, last)),
+            }
+        }
+
+        IntervalSet { ranges: merged }
+    }
+
+    pub fn ranges(&self) -> &[(usize, usize)] {
+        &self.ranges
+    }
+
+    pub fn contains(&self, value: usize) -> bool {
+        let i = self.ranges.partition_point(|&(_, last)| last < value);
+        i < self.ranges.len() && self.ranges[i].0 <= value
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Rule {
     field_name: String,
-    fst_valid_first_idx: usize,
-    fst_valid_last_idx: usize,
-    snd_valid_first_idx: usize,
-    snd_valid_last_idx: usize,
+    valid: IntervalSet,
+}
+
+impl Rule {
+    pub fn new(field_name: &str, valid: IntervalSet) -> Self {
+        Rule {
+            field_name: field_name.to_string(),
+            valid,
+        }
+    }
+
+    pub fn field_name(&self) -> &str {
+        &self.field_name
+    }
+
+    pub fn is_valid(&self, value: usize) -> bool {
+        self.valid.contains(value)
+    }
+}
+
+// `name: a-b or c or d-e`, with any number of ranges or single values
+fn parse_rule(line: &str) -> Option<Rule> {
+    // Only plain digits, parse would take a leading + as well
+    let number = |s: &str| {
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse::<usize>().ok()
+        } else {
+            None
+        }
+    };
+    let (name, ranges) = line.split_once(": ")?;
+
+    let valid = ranges
+        .split(" or ")
+        .map(|r| {
+            let (first, last) = match r.trim().split_once('-') {
+                Some((first, last)) => (number(first)?, number(last)?),
+                None => {
+                    let value = number(r.trim())?;
+                    (value, value)
+                }
+            };
+            if first <= last {
+                Some((first, last))
+            } else {
+                None
+            }
+        })
+        .collect::<Option<Vec<(usize, usize)>>>()?;
+
+    Some(Rule::new(name, IntervalSet::new(valid)))
 }
 
 #[derive(Debug, PartialEq)]
@@ -14,101 +96,282 @@ pub struct Ticket {
     fields: Vec<usize>,
 }
 
-#[aoc_generator(day16)]
-pub fn input_generator(input: &str) -> (Vec<Ticket>, Vec<Rule>) {
-    let rules_regex = Regex::new(r"(.*): (\d+)-(\d+) or (\d+)-(\d+)").unwrap();
+#[derive(Debug, PartialEq)]
+pub struct TicketDocument {
+    pub rules: Vec<Rule>,
+    pub yours: Ticket,
+    pub nearby: Vec<Ticket>,
+}
+
+// The sections in the order they come in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Section {
+    Rules,
+    Yours,
+    Nearby,
+}
+
+// Lines count from 1
+#[derive(Debug, PartialEq)]
+pub enum DocumentError {
+    // The document ended, or the next header came, before this section had
+    // anything in it
+    MissingSection {
+        section: Section,
+    },
+    // This section's header showed up where it shouldn't
+    OutOfOrder {
+        line: usize,
+        section: Section,
+    },
+    UnknownHeader {
+        line: usize,
+    },
+    InvalidRule {
+        line: usize,
+    },
+    InvalidTicket {
+        line: usize,
+    },
+    // Every ticket needs as many values as yours has
+    WrongLength {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    // Something after your ticket, before the nearby ones
+    UnexpectedLine {
+        line: usize,
+    },
+}
+
+fn parse_ticket(line: &str) -> Option<Ticket> {
+    let fields = line
+        .split(',')
+        .map(|c| c.trim().parse::<usize>().ok())
+        .collect::<Option<Vec<usize>>>()?;
+    Some(Ticket { fields })
+}
+
+// Rules, then `your ticket:` and a single ticket, then `nearby tickets:` and
+// any number of them. Blank lines between sections don't matter.
+pub fn parse_document(input: &str) -> Result<TicketDocument, DocumentError> {
+    let mut section = Section::Rules;
     let mut rules: Vec<Rule> = Vec::new();
-    let mut tickets = Vec::new();
-    let mut tickets_stage = false;
+    let mut yours: Option<Ticket> = None;
+    let mut nearby = Vec::new();
 
-    for l in input.lines() {
-        if l == "your ticket:" || l == "nearby tickets:" || l == "" {
-            tickets_stage = true;
-            continue;
+    for (i, l) in input.lines().enumerate() {
+        let (line, l) = (i + 1, l.trim());
+        match l {
+            "" => continue,
+            "your ticket:" => {
+                if section != Section::Rules {
+                    return Err(DocumentError::OutOfOrder {
+                        line,
+                        section: Section::Yours,
+                    });
+                }
+                if rules.is_empty() {
+                    return Err(DocumentError::MissingSection {
+                        section: Section::Rules,
+                    });
+                }
+                section = Section::Yours;
+                continue;
+            }
+            "nearby tickets:" => {
+                if section != Section::Yours {
+                    return Err(DocumentError::OutOfOrder {
+                        line,
+                        section: Section::Nearby,
+                    });
+                }
+                if yours.is_none() {
+                    return Err(DocumentError::MissingSection {
+                        section: Section::Yours,
+                    });
+                }
+                section = Section::Nearby;
+                continue;
+            }
+            _ if l.ends_with(':') => return Err(DocumentError::UnknownHeader { line }),
+            _ => (),
         }
 
-        if tickets_stage {
-            tickets.push(Ticket {
-                fields: l.split(",").map(|c| c.parse::<usize>().unwrap()).collect(),
-            });
-        } else {
-            let captures = rules_regex.captures(l).unwrap();
-            rules.push(Rule {
-                field_name: captures[1].to_string(),
-                fst_valid_first_idx: captures[2].parse::<usize>().unwrap(),
-                fst_valid_last_idx: captures[3].parse::<usize>().unwrap(),
-                snd_valid_first_idx: captures[4].parse::<usize>().unwrap(),
-                snd_valid_last_idx: captures[5].parse::<usize>().unwrap(),
-            });
+        match section {
+            Section::Rules => rules.push(parse_rule(l).ok_or(DocumentError::InvalidRule { line })?),
+            Section::Yours if yours.is_some() => {
+                return Err(DocumentError::UnexpectedLine { line })
+            }
+            _ => {
+                let ticket = parse_ticket(l).ok_or(DocumentError::InvalidTicket { line })?;
+                match &yours {
+                    Some(y) if y.fields.len() != ticket.fields.len() => {
+                        return Err(DocumentError::WrongLength {
+                            line,
+                            expected: y.fields.len(),
+                            found: ticket.fields.len(),
+                        })
+                    }
+                    Some(_) => nearby.push(ticket),
+                    None => yours = Some(ticket),
+                }
+            }
         }
     }
 
-    (tickets, rules)
+    match (section, yours) {
+        (Section::Nearby, Some(yours)) => Ok(TicketDocument {
+            rules,
+            yours,
+            nearby,
+        }),
+        (Section::Rules, _) if rules.is_empty() => Err(DocumentError::MissingSection {
+            section: Section::Rules,
+        }),
+        (Section::Rules, _) | (Section::Yours, None) => Err(DocumentError::MissingSection {
+            section: Section::Yours,
+        }),
+        _ => Err(DocumentError::MissingSection {
+            section: Section::Nearby,
+        }),
+    }
+}
+
+#[aoc_generator(day16)]
+pub fn input_generator(input: &str) -> TicketDocument {
+    parse_document(input).unwrap_or_else(|e| panic!("Invalid input: {:?}", e))
 }
 
 #[aoc(day16, part1)]
-pub fn part1(input: &(Vec<Ticket>, Vec<Rule>)) -> usize {
-    remove_invalid(&input.0, &input.1).0
+pub fn part1(input: &TicketDocument) -> usize {
+    remove_invalid(&input.nearby, &input.rules).0
 }
 
 #[aoc(day16, part2)]
-pub fn part2(input: &(Vec<Ticket>, Vec<Rule>)) -> usize {
-    let valid_idxs = remove_invalid(&input.0, &input.1).1;
-    let mut possible: Vec<Vec<bool>> = vec![vec![true; input.1.len()]; input.0[0].fields.len()];
-
-    for i in 1..valid_idxs.len() {
-        let ticket = &input.0[valid_idxs[i]];
-
-        for (field_num, field) in ticket.fields.iter().enumerate() {
-            for (rule_num, rule) in input.1.iter().enumerate() {
-                if !((field >= &rule.fst_valid_first_idx && field <= &rule.fst_valid_last_idx)
-                    || (field >= &rule.snd_valid_first_idx && field <= &rule.snd_valid_last_idx))
-                {
-                    // We could terminate this early
-                    possible[field_num][rule_num] = false;
-                }
-            }
+pub fn part2(input: &TicketDocument) -> usize {
+    let mapping = validity_report(input)
+        .mapping
+        .unwrap_or_else(|e| panic!("Can't tell the fields apart: {:?}", e));
+
+    mapping
+        .iter()
+        .filter(|(name, _)| name.contains("departure"))
+        .map(|(_, &column)| input.yours.fields[column])
+        .product()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidTicket {
+    // Position among the nearby tickets
+    pub index: usize,
+    // The values that don't fit any rule
+    pub values: Vec<usize>,
+}
+
+// Rules that still fit each column after a valid nearby ticket was looked at
+#[derive(Debug, PartialEq)]
+pub struct EliminationStep {
+    pub ticket: usize,
+    pub candidates: Vec<Vec<String>>,
+}
+
+// Everything part 1 and part 2 go through to get their answers
+#[derive(Debug, PartialEq)]
+pub struct ValidityReport {
+    pub error_rate: usize,
+    pub invalid: Vec<InvalidTicket>,
+    pub steps: Vec<EliminationStep>,
+    pub mapping: Result<HashMap<String, usize>, FieldError>,
+}
+
+pub fn validity_report(document: &TicketDocument) -> ValidityReport {
+    let mut possible = vec![vec![true; document.rules.len()]; document.yours.fields.len()];
+    let mut invalid = Vec::new();
+    let mut steps = Vec::new();
+
+    for (index, ticket) in document.nearby.iter().enumerate() {
+        let values: Vec<usize> = ticket
+            .fields
+            .iter()
+            .copied()
+            .filter(|&v| !document.rules.iter().any(|r| r.is_valid(v)))
+            .collect();
+        if !values.is_empty() {
+            invalid.push(InvalidTicket { index, values });
+            continue;
         }
+
+        eliminate(&mut possible, ticket, &document.rules);
+        let candidates = possible
+            .iter()
+            .map(|column| {
+                document
+                    .rules
+                    .iter()
+                    .zip(column)
+                    .filter(|(_, &p)| p)
+                    .map(|(r, _)| r.field_name.clone())
+                    .collect()
+            })
+            .collect();
+        steps.push(EliminationStep {
+            ticket: index,
+            candidates,
+        });
     }
 
-    loop {
-        let mut match_count = 0;
-
-        for i in 0..possible.len() {
-            let (is_single, idx) = single_true(&possible[i]);
-            if is_single {
-                match_count += 1;
-                for (curr_idx, p) in possible.iter_mut().enumerate() {
-                    // Do no clear the field itself
-                    if curr_idx == i {
-                        continue;
-                    }
-                    p[idx] = false;
-                }
-            }
+    ValidityReport {
+        error_rate: invalid.iter().flat_map(|t| t.values.iter()).sum(),
+        invalid,
+        steps,
+        mapping: assign_fields(&possible, &document.rules),
+    }
+}
+
+impl fmt::Display for ValidityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Error rate: {}", self.error_rate)?;
+        for t in self.invalid.iter() {
+            let values: Vec<String> = t.values.iter().map(|v| v.to_string()).collect();
+            writeln!(f, "Ticket {} is invalid: {}", t.index, values.join(", "))?;
         }
 
-        if match_count >= possible.len() {
-            break;
+        for step in self.steps.iter() {
+            writeln!(f, "After ticket {}:", step.ticket)?;
+            for (column, candidates) in step.candidates.iter().enumerate() {
+                writeln!(f, "  column {}: {}", column, candidates.join(", "))?;
+            }
         }
-    }
 
-    // After this point there needs to be only one true per field in possible
-    let mut result = 1;
-    for (i, r) in input.1.iter().enumerate() {
-        if r.field_name.contains("departure") {
-            for (dep_idx, f) in possible.iter().enumerate() {
-                if f[i] == true {
-                    result *= input.0[0].fields[dep_idx];
+        match &self.mapping {
+            Ok(mapping) => {
+                let mut fields: Vec<(&String, &usize)> = mapping.iter().collect();
+                fields.sort_by_key(|&(_, column)| column);
+                writeln!(f, "Fields:")?;
+                for (name, column) in fields {
+                    writeln!(f, "  column {}: {}", column, name)?;
                 }
+                Ok(())
             }
+            Err(e) => writeln!(f, "No field mapping: {:?}", e),
         }
     }
-
-    result
 }
 
 // Helpers
+fn eliminate(possible: &mut [Vec<bool>], ticket: &Ticket, rules: &[Rule]) {
+    for (field_num, field) in ticket.fields.iter().enumerate() {
+        for (rule_num, rule) in rules.iter().enumerate() {
+            if !rule.is_valid(*field) {
+                // We could terminate this early
+                possible[field_num][rule_num] = false;
+            }
+        }
+    }
+}
+
 fn remove_invalid(tickets: &Vec<Ticket>, rules: &Vec<Rule>) -> (usize, Vec<usize>) {
     let mut error_count = 0;
     let mut idx_vec = Vec::new();
@@ -119,9 +382,7 @@ fn remove_invalid(tickets: &Vec<Ticket>, rules: &Vec<Rule>) -> (usize, Vec<usize
         for field in ticket.fields.iter() {
             let mut is_field_valid = false;
             for rule in rules.iter() {
-                if (field >= &rule.fst_valid_first_idx && field <= &rule.fst_valid_last_idx)
-                    || (field >= &rule.snd_valid_first_idx && field <= &rule.snd_valid_last_idx)
-                {
+                if rule.is_valid(*field) {
                     is_field_valid = true;
                     break;
                 }
@@ -141,21 +402,257 @@ fn remove_invalid(tickets: &Vec<Ticket>, rules: &Vec<Rule>) -> (usize, Vec<usize
     (error_count, idx_vec)
 }
 
-fn single_true(input: &Vec<bool>) -> (bool, usize) {
-    let mut true_count = 0;
-    let mut true_idx = 0;
-    for (i, b) in input.iter().enumerate() {
-        if b == &true {
-            true_count += 1;
-            true_idx = i;
+#[derive(Debug, PartialEq)]
+pub enum FieldError {
+    // These fields can't all get a column of their own
+    Infeasible { fields: Vec<String> },
+    // These fields fit more than one way
+    Ambiguous { fields: Vec<String> },
+}
+
+// Which column every field is in, `possible[column][rule]` saying whether all
+// the values in that column fit the rule. Fields that only fit one column (or
+// columns that only fit one field) settle most of it; if that gets stuck we
+// fall back to a maximum matching, which also tells us what went wrong when
+// there's no single answer.
+pub fn assign_fields(
+    possible: &[Vec<bool>],
+    rules: &[Rule],
+) -> Result<HashMap<String, usize>, FieldError> {
+    let columns = match propagate(possible, rules.len()) {
+        Some(columns) => columns,
+        None => match_fields(possible, rules)?,
+    };
+
+    Ok(rules
+        .iter()
+        .zip(columns)
+        .map(|(r, c)| (r.field_name.clone(), c))
+        .collect())
+}
+
+// Assigns every field that has a single column left (and, when there are as
+// many columns as fields, every column that has a single field left) until
+// nothing changes. Each of those is forced, so if this gets everything the
+// answer is the only one.
+fn propagate(possible: &[Vec<bool>], fields: usize) -> Option<Vec<usize>> {
+    let square = possible.len() == fields;
+    let mut assigned: Vec<Option<usize>> = vec![None; fields];
+    let mut taken = vec![false; possible.len()];
+
+    loop {
+        let mut progress = false;
+
+        for r in 0..fields {
+            if assigned[r].is_some() {
+                continue;
+            }
+            let mut left = (0..possible.len()).filter(|&c| !taken[c] && possible[c][r]);
+            if let (Some(c), None) = (left.next(), left.next()) {
+                assigned[r] = Some(c);
+                taken[c] = true;
+                progress = true;
+            }
+        }
+
+        if square {
+            for c in 0..possible.len() {
+                if taken[c] {
+                    continue;
+                }
+                let mut left = (0..fields).filter(|&r| assigned[r].is_none() && possible[c][r]);
+                if let (Some(r), None) = (left.next(), left.next()) {
+                    assigned[r] = Some(c);
+                    taken[c] = true;
+                    progress = true;
+                }
+            }
+        }
+
+        if !progress {
+            break;
+        }
+    }
+
+    assigned.into_iter().collect()
+}
+
+fn match_fields(possible: &[Vec<bool>], rules: &[Rule]) -> Result<Vec<usize>, FieldError> {
+    let adjacency: Vec<Vec<usize>> = (0..rules.len())
+        .map(|r| (0..possible.len()).filter(|&c| possible[c][r]).collect())
+        .collect();
+    let (field_match, column_match) = max_matching(&adjacency, possible.len());
+
+    check_matching(&adjacency, &field_match, &column_match).map_err(|(conflict, fields)| {
+        let fields = fields
+            .iter()
+            .map(|&r| rules[r].field_name.clone())
+            .collect();
+        match conflict {
+            Conflict::Infeasible => FieldError::Infeasible { fields },
+            Conflict::Ambiguous => FieldError::Ambiguous { fields },
+        }
+    })?;
+
+    Ok(field_match.into_iter().map(Option::unwrap).collect())
+}
+
+// Maximum matching of fields to columns (Hopcroft-Karp): a BFS from the
+// unmatched fields finds the length of the shortest augmenting paths, then a
+// DFS augments along as many disjoint ones of that length as it can, until
+// there are none left
+fn max_matching(
+    adjacency: &[Vec<usize>],
+    columns: usize,
+) -> (Vec<Option<usize>>, Vec<Option<usize>>) {
+    let mut field_match = vec![None; adjacency.len()];
+    let mut column_match = vec![None; columns];
+
+    loop {
+        let mut layer = vec![usize::MAX; adjacency.len()];
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        for (f, m) in field_match.iter().enumerate() {
+            if m.is_none() {
+                layer[f] = 0;
+                queue.push_back(f);
+            }
+        }
+
+        let mut found = false;
+        while let Some(f) = queue.pop_front() {
+            for &c in adjacency[f].iter() {
+                match column_match[c] {
+                    None => found = true,
+                    Some(g) if layer[g] == usize::MAX => {
+                        layer[g] = layer[f] + 1;
+                        queue.push_back(g);
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        if !found {
+            break;
+        }
+
+        for f in 0..adjacency.len() {
+            if field_match[f].is_none() {
+                augment(
+                    f,
+                    adjacency,
+                    &mut layer,
+                    &mut field_match,
+                    &mut column_match,
+                );
+            }
+        }
+    }
+
+    (field_match, column_match)
+}
 
-            if true_count > 1 {
-                return (false, 0);
+fn augment(
+    f: usize,
+    adjacency: &[Vec<usize>],
+    layer: &mut [usize],
+    field_match: &mut [Option<usize>],
+    column_match: &mut [Option<usize>],
+) -> bool {
+    for &c in adjacency[f].iter() {
+        let free = match column_match[c] {
+            None => true,
+            Some(g) => {
+                layer[g] == layer[f] + 1 && augment(g, adjacency, layer, field_match, column_match)
             }
+        };
+        if free {
+            field_match[f] = Some(c);
+            column_match[c] = Some(f);
+            return true;
         }
     }
 
-    (true, true_idx)
+    // Dead end, don't come back here in this round
+    layer[f] = usize::MAX;
+    false
+}
+
+enum Conflict {
+    Infeasible,
+    Ambiguous,
+}
+
+// Ok if every field got a column and there's no other way to do it, otherwise
+// the fields involved, in rule order
+fn check_matching(
+    adjacency: &[Vec<usize>],
+    field_match: &[Option<usize>],
+    column_match: &[Option<usize>],
+) -> Result<(), (Conflict, Vec<usize>)> {
+    // Starting from the unmatched fields, alternate between any column they
+    // fit and the field that took it. Every column reached is taken (otherwise
+    // the matching wasn't maximum), so these fields have too few columns.
+    let mut reached = vec![false; adjacency.len()];
+    let mut queue: VecDeque<usize> = (0..adjacency.len())
+        .filter(|&f| field_match[f].is_none())
+        .collect();
+    for &f in queue.iter() {
+        reached[f] = true;
+    }
+    while let Some(f) = queue.pop_front() {
+        for &c in adjacency[f].iter() {
+            if let Some(g) = column_match[c] {
+                if !reached[g] {
+                    reached[g] = true;
+                    queue.push_back(g);
+                }
+            }
+        }
+    }
+    if reached.contains(&true) {
+        return Err((Conflict::Infeasible, indices(&reached)));
+    }
+
+    // f can move to g's column if f fits it, and then g has to move too. A
+    // field can move for good if that ends with a field moving to a free
+    // column, or comes back around to it.
+    let free = |f: usize| adjacency[f].iter().any(|&c| column_match[c].is_none());
+    let moves = |f: usize| {
+        adjacency[f]
+            .iter()
+            .filter_map(move |&c| column_match[c].filter(|&g| g != f))
+    };
+    let ambiguous: Vec<bool> = (0..adjacency.len())
+        .map(|start| {
+            let mut seen = vec![false; adjacency.len()];
+            let mut stack = vec![start];
+            while let Some(f) = stack.pop() {
+                if free(f) {
+                    return true;
+                }
+                for g in moves(f) {
+                    if g == start {
+                        return true;
+                    }
+                    if !seen[g] {
+                        seen[g] = true;
+                        stack.push(g);
+                    }
+                }
+            }
+            false
+        })
+        .collect();
+    if ambiguous.contains(&true) {
+        return Err((Conflict::Ambiguous, indices(&ambiguous)));
+    }
+
+    Ok(())
+}
+
+fn indices(flags: &[bool]) -> Vec<usize> {
+    (0..flags.len()).filter(|&i| flags[i]).collect()
 }
 
 #[cfg(test)]
@@ -177,11 +674,16 @@ mod tests {
                               55,2,20\n\
                               38,6,12"
             ),
-            (
-                vec![
-                    Ticket {
-                        fields: vec![7, 1, 14],
-                    },
+            TicketDocument {
+                rules: vec![
+                    Rule::new("class", IntervalSet::new(vec![(1, 3), (5, 7)])),
+                    Rule::new("row", IntervalSet::new(vec![(6, 11), (33, 44)])),
+                    Rule::new("seat", IntervalSet::new(vec![(13, 40), (45, 50)])),
+                ],
+                yours: Ticket {
+                    fields: vec![7, 1, 14],
+                },
+                nearby: vec![
                     Ticket {
                         fields: vec![7, 3, 47],
                     },
@@ -195,30 +697,7 @@ mod tests {
                         fields: vec![38, 6, 12],
                     }
                 ],
-                vec![
-                    Rule {
-                        field_name: "class".to_string(),
-                        fst_valid_first_idx: 1,
-                        fst_valid_last_idx: 3,
-                        snd_valid_first_idx: 5,
-                        snd_valid_last_idx: 7,
-                    },
-                    Rule {
-                        field_name: "row".to_string(),
-                        fst_valid_first_idx: 6,
-                        fst_valid_last_idx: 11,
-                        snd_valid_first_idx: 33,
-                        snd_valid_last_idx: 44,
-                    },
-                    Rule {
-                        field_name: "seat".to_string(),
-                        fst_valid_first_idx: 13,
-                        fst_valid_last_idx: 40,
-                        snd_valid_first_idx: 45,
-                        snd_valid_last_idx: 50,
-                    },
-                ]
-            )
+            }
         );
     }
 
@@ -243,7 +722,7 @@ mod tests {
 
     #[test]
     fn test_idx_vec() {
-        let (tickets, rules) = input_generator(
+        let document = input_generator(
             "class: 1-3 or 5-7\n\
                    row: 6-11 or 33-44\n\
                    seat: 13-40 or 45-50\n\n\
@@ -256,6 +735,252 @@ mod tests {
                    38,6,12",
         );
 
-        assert_eq!(remove_invalid(&tickets, &rules).1, vec![0, 1]);
+        assert_eq!(remove_invalid(&document.nearby, &document.rules).1, vec![0]);
+    }
+
+    #[test]
+    fn test_part2() {
+        let document = input_generator(
+            "class: 0-1 or 4-19\n\
+             departure row: 0-5 or 8-19\n\
+             departure seat: 0-13 or 16-19\n\n\
+             your ticket:\n\
+             11,12,13\n\n\
+             nearby tickets:\n\
+             3,9,18\n\
+             15,1,5\n\
+             5,14,9",
+        );
+        assert_eq!(part2(&document), 11 * 13);
+    }
+
+    fn named(names: &[&str]) -> Vec<Rule> {
+        names
+            .iter()
+            .map(|n| Rule::new(n, IntervalSet::default()))
+            .collect()
+    }
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_assign_fields() {
+        let rules = named(&["a", "b", "c"]);
+
+        let possible = vec![
+            vec![true, true, false],
+            vec![false, true, false],
+            vec![true, true, true],
+        ];
+        assert_eq!(
+            assign_fields(&possible, &rules),
+            Ok(vec![
+                ("a".to_string(), 0),
+                ("b".to_string(), 1),
+                ("c".to_string(), 2)
+            ]
+            .into_iter()
+            .collect())
+        );
+
+        // a is settled, b and c could go either way
+        let possible = vec![
+            vec![true, false, false],
+            vec![false, true, true],
+            vec![false, true, true],
+        ];
+        assert_eq!(
+            assign_fields(&possible, &rules),
+            Err(FieldError::Ambiguous {
+                fields: strings(&["b", "c"])
+            })
+        );
+
+        // a and b both only fit the first column
+        let possible = vec![
+            vec![true, true, false],
+            vec![false, false, true],
+            vec![false, false, true],
+        ];
+        assert_eq!(
+            assign_fields(&possible, &rules),
+            Err(FieldError::Infeasible {
+                fields: strings(&["a", "b"])
+            })
+        );
+
+        // More columns than fields: b has the middle one to itself, but a can
+        // take either of the others
+        let rules = named(&["a", "b"]);
+        let possible = vec![vec![true, false], vec![false, true], vec![true, false]];
+        assert_eq!(
+            assign_fields(&possible, &rules),
+            Err(FieldError::Ambiguous {
+                fields: strings(&["a"])
+            })
+        );
+        let possible = vec![vec![true, true], vec![false, true], vec![false, false]];
+        assert_eq!(
+            assign_fields(&possible, &rules),
+            Ok(vec![("a".to_string(), 0), ("b".to_string(), 1)]
+                .into_iter()
+                .collect())
+        );
+    }
+
+    #[test]
+    fn test_rules() {
+        let rule = parse_rule("zone: 10-20 or 5 or 1-3 or 4-4 or 15-30").unwrap();
+        assert_eq!(rule.field_name(), "zone");
+        assert_eq!(rule.valid.ranges(), &[(1, 5), (10, 30)]);
+        for v in [0, 6, 9, 31].iter() {
+            assert!(!rule.is_valid(*v));
+        }
+        for v in [1, 4, 5, 10, 25, 30].iter() {
+            assert!(rule.is_valid(*v));
+        }
+
+        let rule = parse_rule("departure time: 7").unwrap();
+        assert_eq!(rule.valid.ranges(), &[(7, 7)]);
+
+        assert_eq!(parse_rule("row: 6-11 or"), None);
+        assert_eq!(parse_rule("row: 11-6"), None);
+        assert_eq!(parse_rule("row 6-11"), None);
+        assert_eq!(parse_rule("row: +6-11"), None);
+        assert_eq!(parse_rule("row: 6--11"), None);
+
+        let set = IntervalSet::new(vec![(5, 3), (0, 0), (usize::MAX, usize::MAX), (1, 2)]);
+        assert_eq!(set.ranges(), &[(0, 2), (usize::MAX, usize::MAX)]);
+        assert!(set.contains(usize::MAX));
+        assert!(!set.contains(4));
+        assert!(!IntervalSet::default().contains(0));
+    }
+
+    #[test]
+    fn test_document_errors() {
+        let parse = |lines: &[&str]| parse_document(&lines.join("\n"));
+        let missing = |section| Err(DocumentError::MissingSection { section });
+
+        // Blank lines are optional and there can be no nearby tickets
+        assert!(parse(&["a: 1-3", "your ticket:", "1", "nearby tickets:"]).is_ok());
+
+        assert_eq!(parse(&[]), missing(Section::Rules));
+        assert_eq!(parse(&["your ticket:", "1"]), missing(Section::Rules));
+        assert_eq!(parse(&["a: 1-3"]), missing(Section::Yours));
+        assert_eq!(
+            parse(&["a: 1-3", "your ticket:", "nearby tickets:", "1"]),
+            missing(Section::Yours)
+        );
+        assert_eq!(
+            parse(&["a: 1-3", "your ticket:", "1"]),
+            missing(Section::Nearby)
+        );
+
+        assert_eq!(
+            parse(&[
+                "a: 1-3",
+                "",
+                "nearby tickets:",
+                "1",
+                "",
+                "your ticket:",
+                "1"
+            ]),
+            Err(DocumentError::OutOfOrder {
+                line: 3,
+                section: Section::Nearby
+            })
+        );
+        assert_eq!(
+            parse(&["a: 1-3", "your ticket:", "1", "your ticket:", "1"]),
+            Err(DocumentError::OutOfOrder {
+                line: 4,
+                section: Section::Yours
+            })
+        );
+        assert_eq!(
+            parse(&["a: 1-3", "their ticket:", "1"]),
+            Err(DocumentError::UnknownHeader { line: 2 })
+        );
+        assert_eq!(
+            parse(&["a: 1-3", "b 4-5"]),
+            Err(DocumentError::InvalidRule { line: 2 })
+        );
+        assert_eq!(
+            parse(&["a: 1-3", "your ticket:", "1,x"]),
+            Err(DocumentError::InvalidTicket { line: 3 })
+        );
+        assert_eq!(
+            parse(&["a: 1-3", "your ticket:", "1", "2"]),
+            Err(DocumentError::UnexpectedLine { line: 4 })
+        );
+        assert_eq!(
+            parse(&["a: 1-3", "your ticket:", "1", "nearby tickets:", "1", "1,2"]),
+            Err(DocumentError::WrongLength {
+                line: 6,
+                expected: 1,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_validity_report() {
+        let report = validity_report(&input_generator(
+            "class: 0-1 or 4-19\n\
+             row: 0-5 or 8-19\n\
+             seat: 0-13 or 16-19\n\n\
+             your ticket:\n\
+             11,12,13\n\n\
+             nearby tickets:\n\
+             3,9,18\n\
+             20,1,25\n\
+             15,1,5\n\
+             5,14,9",
+        ));
+
+        assert_eq!(report.error_rate, 45);
+        assert_eq!(
+            report.invalid,
+            vec![InvalidTicket {
+                index: 1,
+                values: vec![20, 25]
+            }]
+        );
+        assert_eq!(
+            report
+                .steps
+                .iter()
+                .map(|s| s.ticket)
+                .collect::<Vec<usize>>(),
+            vec![0, 2, 3]
+        );
+        assert_eq!(
+            report.steps[0].candidates,
+            vec![
+                vec!["row", "seat"],
+                vec!["class", "row", "seat"],
+                vec!["class", "row", "seat"]
+            ]
+        );
+        assert_eq!(
+            report.steps[2].candidates,
+            vec![
+                vec!["row"],
+                vec!["class", "row"],
+                vec!["class", "row", "seat"]
+            ]
+        );
+        assert_eq!(
+            report.to_string(),
+            "Error rate: 45\n\
+             Ticket 1 is invalid: 20, 25\n\
+             After ticket 0:\n  column 0: row, seat\n  column 1: class, row, seat\n  column 2: class, row, seat\n\
+             After ticket 2:\n  column 0: row\n  column 1: class, row, seat\n  column 2: class, row, seat\n\
+             After ticket 3:\n  column 0: row\n  column 1: class, row\n  column 2: class, row, seat\n\
+             Fields:\n  column 0: row\n  column 1: class\n  column 2: seat\n"
+        );
     }
 }
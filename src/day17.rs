@@ -6,147 +6,166 @@ pub enum Cube {
     Inactive,
 }
 
-pub struct Dimension {
-    state_map: HashMap<(i32, i32, i32, i32), Cube>,
-    neighbours_map: HashMap<(i32, i32, i32, i32), usize>,
+impl Cube {
+    // What a cube turns into given how many of its neighbours are active, None
+    // meaning it's inactive and doesn't need to be kept around
+    fn next(current: Option<&Cube>, active_neighbours: usize) -> Option<Cube> {
+        match (current, active_neighbours) {
+            (Some(Cube::Active), 2) | (_, 3) => Some(Cube::Active),
+            _ => None,
+        }
+    }
 }
 
-impl Dimension {
-    pub fn process_neighbours(&mut self) {
-        // Clear everything on start
-        self.neighbours_map = HashMap::new();
-
-        // Compute the number of neighbours for all cells
-        for (coord, state) in &self.state_map {
-            // Increment in all 3 dimensions
-            for i in -1..2 {
-                for j in -1..2 {
-                    for k in -1..2 {
-                        if state == &Cube::Active {
-                            if i == 0 && j == 0 && k == 0 {
-                                continue;
-                            }
-
-                            let new_coord = (coord.0 + i, coord.1 + j, coord.2 + k, 0);
-                            if !self.neighbours_map.contains_key(&new_coord) {
-                                self.neighbours_map.insert(new_coord, 1);
-                            } else {
-                                self.neighbours_map
-                                    .insert(new_coord, self.neighbours_map[&new_coord] + 1);
-                            }
-                        }
-                    }
-                }
+// Every offset to a neighbour in N dimensions, so all 3^N - 1 combinations of
+// -1, 0 and 1 except all zeroes
+pub fn neighbour_offsets<const N: usize>() -> Vec<[i32; N]> {
+    let total = 3usize.pow(N as u32);
+    (0..total)
+        .map(|mut n| {
+            let mut offset = [0; N];
+            for o in offset.iter_mut() {
+                *o = (n % 3) as i32 - 1;
+                n /= 3;
             }
+            offset
+        })
+        .filter(|o| o.iter().any(|&d| d != 0))
+        .collect()
+}
+
+// Cubes in N dimensions, the first two axes being the ones of the input slice.
+// When `symmetric` the state is the same on both sides of every other axis, so
+// only cubes with those coordinates >= 0 are kept, each one standing for all
+// of its mirror images.
+pub struct Dimension<const N: usize> {
+    state_map: HashMap<[i32; N], Cube>,
+    neighbours_map: HashMap<[i32; N], usize>,
+    offsets: Vec<[i32; N]>,
+    symmetric: bool,
+}
+
+// How many cubes a kept one stands for: it gets mirrored on every extra axis
+// it's not at 0 on
+fn multiplicity<const N: usize>(coord: &[i32; N]) -> usize {
+    1 << coord[2..].iter().filter(|&&c| c != 0).count()
+}
+
+// How many mirror images of `from` are next to `to`, both having their extra
+// coordinates >= 0. On an axis where `from` is at 1 and `to` at 0, -1 is a
+// neighbour as well.
+fn mirrored_neighbours<const N: usize>(from: &[i32; N], to: &[i32; N]) -> usize {
+    1 << from[2..]
+        .iter()
+        .zip(to[2..].iter())
+        .filter(|&(&f, &t)| f == 1 && t == 0)
+        .count()
+}
+
+impl<const N: usize> Dimension<N> {
+    // Switches to only keeping one side of the extra axes, None if the cubes
+    // aren't actually the same on both sides of them
+    pub fn into_symmetric(mut self) -> Option<Self> {
+        if self.symmetric {
+            return Some(self);
         }
 
-        //Update state_map with the new cells
-        let mut new_states = HashMap::new();
-        for (coord, count) in &self.neighbours_map {
-            if self.state_map.contains_key(&coord) {
-                match self.state_map[&coord] {
-                    Cube::Active => {
-                        if count == &2 || count == &3 {
-                            new_states.insert(*coord, Cube::Active);
-                        }
-                    }
-                    Cube::Inactive => {
-                        if count == &3 {
-                            new_states.insert(*coord, Cube::Active);
-                        }
-                    }
-                }
-            } else {
-                if count == &3 {
-                    new_states.insert(*coord, Cube::Active);
-                }
+        let is_active = |coord: &[i32; N]| self.state_map.get(coord) == Some(&Cube::Active);
+        let kept: HashMap<[i32; N], Cube> = self
+            .state_map
+            .iter()
+            .filter(|&(coord, state)| state == &Cube::Active && coord[2..].iter().all(|&c| c >= 0))
+            .map(|(coord, state)| (*coord, state.clone()))
+            .collect();
+
+        // Every cube's image on the kept side has to be there, and then having
+        // as many cubes as the kept ones stand for means all the images are
+        let all_mirrored = self.state_map.keys().filter(|c| is_active(c)).all(|coord| {
+            let mut image = *coord;
+            for c in image[2..].iter_mut() {
+                *c = c.abs();
             }
+            is_active(&image)
+        });
+        if !all_mirrored || kept.keys().map(multiplicity).sum::<usize>() != self.count_actives() {
+            return None;
         }
 
-        self.state_map = new_states;
+        self.state_map = kept;
+        self.symmetric = true;
+        Some(self)
     }
 
-    pub fn process_neighbours_4d(&mut self) {
+    pub fn process_neighbours(&mut self) {
         // Clear everything on start
         self.neighbours_map = HashMap::new();
 
         // Compute the number of neighbours for all cells
         for (coord, state) in &self.state_map {
-            // Increment in all 3 dimensions
-            for i in -1..2 {
-                for j in -1..2 {
-                    for k in -1..2 {
-                        for l in -1..2 {
-                            if state == &Cube::Active {
-                                if i == 0 && j == 0 && k == 0 && l == 0 {
-                                    continue;
-                                }
-
-                                let new_coord =
-                                    (coord.0 + i, coord.1 + j, coord.2 + k, coord.3 + l);
-                                if !self.neighbours_map.contains_key(&new_coord) {
-                                    self.neighbours_map.insert(new_coord, 1);
-                                } else {
-                                    self.neighbours_map
-                                        .insert(new_coord, self.neighbours_map[&new_coord] + 1);
-                                }
-                            }
-                        }
-                    }
-                }
+            if state != &Cube::Active {
+                continue;
             }
-        }
 
-        //Update state_map with the new cells
-        let mut new_states = HashMap::new();
-        for (coord, count) in &self.neighbours_map {
-            if self.state_map.contains_key(&coord) {
-                match self.state_map[&coord] {
-                    Cube::Active => {
-                        if count == &2 || count == &3 {
-                            new_states.insert(*coord, Cube::Active);
-                        }
-                    }
-                    Cube::Inactive => {
-                        if count == &3 {
-                            new_states.insert(*coord, Cube::Active);
-                        }
-                    }
-                }
-            } else {
-                if count == &3 {
-                    new_states.insert(*coord, Cube::Active);
+            for offset in self.offsets.iter() {
+                let mut new_coord = *coord;
+                for (c, o) in new_coord.iter_mut().zip(offset) {
+                    *c += o;
                 }
+
+                let count = if !self.symmetric {
+                    1
+                } else if new_coord[2..].iter().any(|&c| c < 0) {
+                    // Counted when we get to its image on the kept side
+                    continue;
+                } else {
+                    mirrored_neighbours(coord, &new_coord)
+                };
+                *self.neighbours_map.entry(new_coord).or_insert(0) += count;
             }
         }
 
-        self.state_map = new_states;
+        //Update state_map with the new cells
+        let state_map = &self.state_map;
+        self.state_map = self
+            .neighbours_map
+            .iter()
+            .filter_map(|(coord, &count)| {
+                Cube::next(state_map.get(coord), count).map(|cube| (*coord, cube))
+            })
+            .collect();
     }
 
     pub fn count_actives(&self) -> usize {
-        let mut count = 0;
-
-        for (_, state) in &self.state_map {
-            if state == &Cube::Active {
-                count += 1;
-            }
-        }
-
-        count
+        self.state_map
+            .iter()
+            .filter(|&(_, state)| state == &Cube::Active)
+            .map(|(coord, _)| {
+                if self.symmetric {
+                    multiplicity(coord)
+                } else {
+                    1
+                }
+            })
+            .sum()
     }
 }
 
-pub fn input_generator(input: &str) -> Dimension {
+pub fn input_generator<const N: usize>(input: &str) -> Dimension<N> {
+    assert!(N >= 2, "The input is already two dimensional");
     let mut state_map = HashMap::new();
 
     for (x, l) in input.lines().enumerate() {
         for (y, c) in l.chars().enumerate() {
+            let mut coord = [0; N];
+            coord[0] = x as i32;
+            coord[1] = y as i32;
+
             match c {
                 '.' => {
-                    state_map.insert((x as i32, y as i32, 0 as i32, 0 as i32), Cube::Inactive);
+                    state_map.insert(coord, Cube::Inactive);
                 }
                 '#' => {
-                    state_map.insert((x as i32, y as i32, 0 as i32, 0 as i32), Cube::Active);
+                    state_map.insert(coord, Cube::Active);
                 }
                 _ => panic!("Invalid input!"),
             }
@@ -156,38 +175,53 @@ pub fn input_generator(input: &str) -> Dimension {
     Dimension {
         state_map,
         neighbours_map: HashMap::new(),
+        offsets: neighbour_offsets(),
+        symmetric: false,
     }
 }
 
-#[aoc(day17, part1)]
-pub fn part1(input: &str) -> usize {
-    let mut dimension = input_generator(input);
+// Active cubes after running the input slice for `cycles` in N dimensions
+pub fn simulate<const N: usize>(input: &str, cycles: usize) -> usize {
+    let mut dimension = input_generator::<N>(input);
 
-    for _ in 0..6 {
+    for _ in 0..cycles {
         dimension.process_neighbours();
     }
 
     dimension.count_actives()
 }
 
-#[aoc(day17, part2)]
-pub fn part2(input: &str) -> usize {
-    let mut dimension = input_generator(input);
+// Same as simulate, but making use of the input being flat in the extra axes,
+// which gets much faster the more of them there are
+pub fn simulate_symmetric<const N: usize>(input: &str, cycles: usize) -> usize {
+    let mut dimension = input_generator::<N>(input)
+        .into_symmetric()
+        .expect("A flat slice is always symmetric");
 
-    for _ in 0..6 {
-        dimension.process_neighbours_4d();
+    for _ in 0..cycles {
+        dimension.process_neighbours();
     }
 
     dimension.count_actives()
 }
 
+#[aoc(day17, part1)]
+pub fn part1(input: &str) -> usize {
+    simulate::<3>(input, 6)
+}
+
+#[aoc(day17, part2)]
+pub fn part2(input: &str) -> usize {
+    simulate::<4>(input, 6)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_input() {
-        assert_eq!(input_generator(".#.\n..#\n###").state_map.len(), 9);
+        assert_eq!(input_generator::<3>(".#.\n..#\n###").state_map.len(), 9);
     }
 
     #[test]
@@ -199,4 +233,48 @@ mod tests {
     fn test_part2() {
         assert_eq!(part2(".#.\n..#\n###"), 848);
     }
+
+    #[test]
+    fn test_dimensions() {
+        assert_eq!(neighbour_offsets::<2>().len(), 8);
+        assert_eq!(neighbour_offsets::<6>().len(), 728);
+
+        // In 2D this is plain Life, and the glider keeps its five cells
+        let mut glider = input_generator::<2>(".#.\n..#\n###");
+        for _ in 0..4 {
+            glider.process_neighbours();
+            assert_eq!(glider.count_actives(), 5);
+        }
+        assert!(glider.state_map.contains_key(&[3, 2]));
+
+        // A single cube dies out however many dimensions it has
+        assert_eq!(simulate::<6>("#", 1), 0);
+    }
+
+    #[test]
+    fn test_symmetric() {
+        let input = ".#.\n..#\n###";
+        assert_eq!(simulate_symmetric::<3>(input, 6), 112);
+        assert_eq!(simulate_symmetric::<4>(input, 6), 848);
+        assert_eq!(simulate_symmetric::<2>(input, 4), 5);
+        for cycles in 0..4 {
+            assert_eq!(
+                simulate_symmetric::<5>(input, cycles),
+                simulate::<5>(input, cycles)
+            );
+        }
+        assert_eq!(simulate_symmetric::<6>("#", 1), 0);
+
+        let mut dimension = input_generator::<4>(input);
+        dimension.process_neighbours();
+        assert!(dimension.into_symmetric().is_some());
+
+        // Lopsided in z
+        let mut dimension = input_generator::<3>("#");
+        dimension.state_map.insert([0, 0, 1], Cube::Active);
+        assert!(dimension.into_symmetric().is_none());
+        let mut dimension = input_generator::<3>("#");
+        dimension.state_map.insert([0, 0, -1], Cube::Active);
+        assert!(dimension.into_symmetric().is_none());
+    }
 }
@@ -3,6 +3,7 @@ extern crate aoc_runner;
 #[macro_use]
 extern crate aoc_runner_derive;
 
+pub mod automaton;
 pub mod day1;
 pub mod day10;
 pub mod day11;